@@ -4,11 +4,10 @@ extern crate pretty_env_logger;
 use bincode::{Decode, Encode};
 use log::{error, info, warn};
 use std::io::Write;
-use std::time::Duration;
 
 use clap::Parser;
-use minignetclient::MGNClient;
-use minignetcommon::{Error, GamerIdType, Message, MessageAddress, Response, SessionIdType};
+use minignetclient::{GameClient, GameLoop, GameLoopDriver, GamePhase, MGNClient, TypedClient};
+use minignetcommon::{Error, GamerIdType, Message, MessageAddress, SessionIdType};
 use rand::{prelude::*, rng};
 use tokio::io::{self, AsyncBufReadExt, BufReader};
 
@@ -57,13 +56,6 @@ enum InputCommand {
     Step(Coord),
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum GameState {
-    Init,
-    SelfTurn,
-    OtherTurn,
-}
-
 struct InputParser;
 
 impl InputParser {
@@ -78,7 +70,7 @@ impl InputParser {
         {
             let y = caps.get(1).unwrap().as_str().chars().next().unwrap() as u8 - b'a';
             let x: u8 = caps.get(2).unwrap().as_str().parse().unwrap();
-            return Ok(InputCommand::Step(Coord { x: x - 1, y: y }));
+            return Ok(InputCommand::Step(Coord { x: x - 1, y }));
         }
 
         Err(format!("Unparsable command: {:?}", raw).into())
@@ -89,8 +81,7 @@ struct Game {
     self_board: [CellState; 100],
     other_board: [CellState; 100],
     ship_coords: Vec<Coord>,
-    client: MGNClient,
-    state: GameState,
+    channel: TypedClient<(), TorpedoMessage>,
 }
 
 impl Game {
@@ -131,77 +122,69 @@ impl Game {
             self_board: [CellState::Undiscovered; 100],
             other_board: [CellState::Undiscovered; 100],
             ship_coords,
-            client,
-            state: GameState::Init,
+            channel: TypedClient::new(client),
         }
     }
 
     async fn init(&self) {
-        match self.client.join_session().await {
-            Ok(Response::Ok) => info!("Joined session"),
-            response => panic!("Unexpected response for join: {:?}", response),
-        }
+        self.channel
+            .client()
+            .join_session()
+            .await
+            .expect("Failed joining session");
+        info!("Joined session");
     }
 
-    async fn run(&mut self) {
+    async fn run(&mut self, driver: &mut GameLoopDriver) {
         let mut stdin = BufReader::new(io::stdin()).lines();
 
-        self.refresh_screen();
+        self.refresh_screen(driver.phase());
 
         loop {
             tokio::select! {
                 input_line_result = stdin.next_line() => {
-                    self.handle_input(input_line_result).await;
+                    self.handle_input(input_line_result, driver.phase()).await;
                 }
-                _ = tokio::time::sleep(Duration::from_millis(500)) => {
-                    self.consume_messages().await;
-                    self.watch_for_state_change().await;
+                _ = tokio::time::sleep(driver.poll_interval()) => {
+                    if let Err(err) = driver.step(self).await {
+                        error!("Failed polling the session: {}", err);
+                    }
                 }
             };
         }
     }
 
-    async fn handle_input(&mut self, input_line_result: io::Result<Option<String>>) {
+    async fn handle_input(
+        &mut self,
+        input_line_result: io::Result<Option<String>>,
+        phase: GamePhase,
+    ) {
         match input_line_result {
             Ok(Some(line)) => match InputParser::parse(line) {
                 Ok(cmd) => match cmd {
                     InputCommand::Start => {
-                        if self.state != GameState::Init {
+                        if phase != GamePhase::Waiting {
                             warn!("Cannot start game, already started.");
                             return;
                         }
 
-                        match self.client.start_session().await {
-                            Ok(Response::Ok) => info!("Session start requested"),
-                            response => {
-                                panic!("Unexpected response for session start: {:?}", response)
-                            }
-                        };
+                        self.channel
+                            .client()
+                            .start_session()
+                            .await
+                            .expect("Failed starting session");
+                        info!("Session start requested");
                     }
                     InputCommand::Step(coord) => {
-                        if self.state != GameState::SelfTurn {
+                        if phase != GamePhase::MyTurn {
                             warn!("Cannot guess while not on turn");
                             return;
                         }
 
-                        match self
-                            .client
-                            .send_message(Message {
-                                from: self.client.gamer_id.clone(),
-                                to: MessageAddress::All,
-                                payload: bincode::encode_to_vec(
-                                    TorpedoMessage::Guess(coord),
-                                    bincode::config::standard(),
-                                )
-                                .expect("Failed encoding guess"),
-                            })
+                        self.channel
+                            .send_to(MessageAddress::All, &TorpedoMessage::Guess(coord))
                             .await
-                        {
-                            Ok(Response::Ok) => { /* noop */ }
-                            response => {
-                                panic!("Unexpected respone to SEND-MESSAGE: {:?}", response)
-                            }
-                        }
+                            .expect("Failed sending guess");
                     }
                 },
                 Err(err) => {
@@ -212,110 +195,7 @@ impl Game {
         }
     }
 
-    async fn consume_messages(&mut self) {
-        match self.client.fetch_all_messages().await {
-            Ok(Response::OkWithMessages(messages)) => {
-                for message in messages {
-                    let (torpedo_message, _size): (TorpedoMessage, _) =
-                        bincode::decode_from_slice(&message.payload, bincode::config::standard())
-                            .expect("Failed decoding message payload");
-
-                    info!("Got message: {:?}", &torpedo_message);
-
-                    match torpedo_message {
-                        TorpedoMessage::Guess(coord) => {
-                            let is_hit = self.ship_coords.contains(&coord);
-
-                            self.self_board[coord.singular()] = if is_hit {
-                                CellState::Hit
-                            } else {
-                                CellState::Miss
-                            };
-
-                            match self
-                                .client
-                                .send_message(Message {
-                                    from: self.client.gamer_id.clone(),
-                                    to: MessageAddress::All,
-                                    payload: bincode::encode_to_vec(
-                                        TorpedoMessage::HitOrMissReply(coord, is_hit),
-                                        bincode::config::standard(),
-                                    )
-                                    .expect("Failed encoding hit of miss reply message"),
-                                })
-                                .await
-                            {
-                                Ok(Response::Ok) => { /* noop */ }
-                                response => {
-                                    panic!("Unexpected response to send message: {:?}", response);
-                                }
-                            }
-                        }
-                        TorpedoMessage::HitOrMissReply(coord, is_hit) => {
-                            self.other_board[coord.singular()] = if is_hit {
-                                CellState::Hit
-                            } else {
-                                CellState::Miss
-                            };
-
-                            match self.client.next_gamer().await {
-                                Ok(Response::Ok) => { /* noop */ }
-                                response => {
-                                    panic!("Unexpected response to NEXT-GAMER: {:?}", response);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            response => {
-                error!(
-                    "Unexpected response {:?} for fetching all messages",
-                    response
-                );
-            }
-        }
-    }
-
-    async fn watch_for_state_change(&mut self) {
-        match self.state {
-            GameState::Init => match self.client.is_game_on().await {
-                Ok(Response::OkWithBool(is_game_on)) => {
-                    if is_game_on {
-                        info!("Game session has started");
-                        self.change_state(GameState::OtherTurn);
-                    }
-                }
-                response => panic!("Unexpected response to IS-GAME-ON: {:?}", response),
-            },
-            GameState::SelfTurn => match self.client.is_gamer_turn().await {
-                Ok(Response::OkWithBool(is_my_turn)) => {
-                    if !is_my_turn {
-                        info!("Other player turn");
-                        self.change_state(GameState::OtherTurn);
-                    }
-                }
-                response => panic!("Unexpected response to IS-GAMER-TURN: {:?}", response),
-            },
-            GameState::OtherTurn => match self.client.is_gamer_turn().await {
-                Ok(Response::OkWithBool(is_my_turn)) => {
-                    if is_my_turn {
-                        info!("Self player turn");
-                        self.change_state(GameState::SelfTurn);
-                    }
-                }
-                response => panic!("Unexpected response to IS-GAMER-TURN: {:?}", response),
-            },
-        }
-    }
-
-    fn change_state(&mut self, state: GameState) {
-        info!("Game state change: {:?} -> {:?}", self.state, state);
-        self.state = state;
-        self.refresh_screen();
-    }
-
-    fn refresh_screen(&self) {
+    fn refresh_screen(&self, phase: GamePhase) {
         print!("\x1B[2J\x1B[1;1H");
 
         println!(
@@ -326,7 +206,7 @@ impl Game {
         );
 
         for y in 0..10 {
-            print!("\x1B[93m{}\x1B[0m ", (b'A' + (y as u8)) as char);
+            print!("\x1B[93m{}\x1B[0m ", (b'A' + y) as char);
 
             for x in 0..10 {
                 if self.ship_coords.contains(&Coord { x, y }) {
@@ -350,7 +230,7 @@ impl Game {
 
             print!("        ");
 
-            print!("\x1B[93m{}\x1B[0m ", (b'A' + (y as u8)) as char);
+            print!("\x1B[93m{}\x1B[0m ", (b'A' + y) as char);
             for x in 0..10 {
                 print!("\x1B[90m[\x1B[0m");
                 match self.other_board[(y * 10 + x) as usize] {
@@ -364,16 +244,62 @@ impl Game {
             print!("\n\n");
         }
 
-        match self.state {
-            GameState::Init => print!("Type 'start' to start > "),
-            GameState::SelfTurn => print!("Guess > "),
-            GameState::OtherTurn => print!("... wait for the other player ..."),
+        match phase {
+            GamePhase::Waiting => print!("Type 'start' to start > "),
+            GamePhase::MyTurn => print!("Guess > "),
+            GamePhase::OtherTurn => print!("... wait for the other player ..."),
+            GamePhase::Over => print!("Game over"),
         }
 
         std::io::stdout().flush().unwrap();
     }
 }
 
+impl GameLoop for Game {
+    async fn on_my_turn(&mut self, _client: &MGNClient) -> Result<(), Error> {
+        self.refresh_screen(GamePhase::MyTurn);
+        Ok(())
+    }
+
+    async fn on_other_turn(&mut self, _client: &MGNClient) -> Result<(), Error> {
+        self.refresh_screen(GamePhase::OtherTurn);
+        Ok(())
+    }
+
+    async fn on_message(&mut self, client: &MGNClient, message: Message) -> Result<(), Error> {
+        let torpedo_message = self.channel.decode_message(&message)?;
+        info!("Got message: {:?}", &torpedo_message);
+
+        match torpedo_message {
+            TorpedoMessage::Guess(coord) => {
+                let is_hit = self.ship_coords.contains(&coord);
+
+                self.self_board[coord.singular()] = if is_hit {
+                    CellState::Hit
+                } else {
+                    CellState::Miss
+                };
+
+                self.channel
+                    .send_to(
+                        MessageAddress::All,
+                        &TorpedoMessage::HitOrMissReply(coord, is_hit),
+                    )
+                    .await
+            }
+            TorpedoMessage::HitOrMissReply(coord, is_hit) => {
+                self.other_board[coord.singular()] = if is_hit {
+                    CellState::Hit
+                } else {
+                    CellState::Miss
+                };
+
+                client.next_gamer().await
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
@@ -384,7 +310,8 @@ async fn main() {
     let gamer_id = cmd_line_args.gamer_id.clone();
     let client = MGNClient::new(cmd_line_args.server, session_id, gamer_id).unwrap();
 
+    let mut driver = GameLoopDriver::new(client.clone()).with_update_polling(false);
     let mut game = Game::new(client);
     game.init().await;
-    game.run().await;
+    game.run(&mut driver).await;
 }
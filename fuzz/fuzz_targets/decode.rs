@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use minignetcommon::{
+    Datagram, Hello, Operation, RequestEnvelope, decode_untrusted, from_json_line,
+};
+
+// Everything the server decodes from a connection or a datagram.
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_untrusted::<Operation>(data);
+    let _ = decode_untrusted::<RequestEnvelope>(data);
+    let _ = decode_untrusted::<Hello>(data);
+    let _ = decode_untrusted::<Datagram>(data);
+    let _ = from_json_line::<Operation>(data);
+});
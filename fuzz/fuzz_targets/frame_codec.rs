@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use minignetcommon::{Compression, FrameCodec};
+
+const MAX_SIZE: usize = 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    for compression in Compression::supported() {
+        let codec = FrameCodec::new(Some(compression), 0);
+
+        // Frames from a peer, possibly forged.
+        let _ = codec.decode(data.to_vec(), MAX_SIZE);
+
+        let decoded = codec
+            .decode(codec.encode(data.to_vec()), MAX_SIZE)
+            .expect("Failed decoding an encoded frame");
+        assert_eq!(decoded, data);
+    }
+});
@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use minignetcommon::{MessageLimits, Operation, QueueOverflowPolicy, WorldState, decode_untrusted};
+
+// Applies a sequence of bincode encoded operations, the first byte picks the overflow policy.
+fuzz_target!(|data: &[u8]| {
+    let Some((policy, mut operations)) = data.split_first() else {
+        return;
+    };
+
+    let mut world_state = WorldState::new(MessageLimits {
+        max_queued_messages: 4,
+        max_payload_size: 64,
+        overflow_policy: if policy % 2 == 0 {
+            QueueOverflowPolicy::RejectSender
+        } else {
+            QueueOverflowPolicy::DropOldest
+        },
+        max_fetch_size: 128,
+    });
+
+    while let Ok((operation, size)) = decode_untrusted::<Operation>(operations) {
+        world_state.handle(operation);
+        operations = &operations[size..];
+    }
+});
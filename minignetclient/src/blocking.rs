@@ -0,0 +1,385 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use minignetcommon::{Error, GamerIdType, Message, Operation, Response, SessionIdType, TeamIdType};
+
+use crate::{
+    ClientConfig, ClientError, ServerAddress,
+    error::{expect_bool, expect_messages, expect_ok, expect_previous_round_updates, expect_teams},
+    one_shot,
+};
+
+/// A connection to the server, over TCP or a Unix domain socket.
+trait ServerStream: Read + Write {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()>;
+
+    fn shutdown_write(&self) -> std::io::Result<()>;
+}
+
+impl ServerStream for TcpStream {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+
+    fn shutdown_write(&self) -> std::io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+#[cfg(unix)]
+impl ServerStream for UnixStream {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+
+    fn shutdown_write(&self) -> std::io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+/// Synchronous counterpart of `MGNClient` using std sockets, for game loops that do not run
+/// inside an async runtime.
+#[derive(Clone)]
+pub struct MGNClientBlocking {
+    config: ClientConfig,
+    server_address: ServerAddress,
+    resolved_addrs: Arc<Mutex<Vec<SocketAddr>>>,
+    pub session_id: SessionIdType,
+    pub gamer_id: GamerIdType,
+}
+
+impl MGNClientBlocking {
+    pub fn new<Addr>(
+        addr: Addr,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+    ) -> Result<Self, std::io::Error>
+    where
+        Addr: Into<ServerAddress>,
+    {
+        let server_address = addr.into();
+        let resolved_addrs = server_address.resolve()?;
+
+        Ok(Self {
+            config: ClientConfig::default(),
+            server_address,
+            resolved_addrs: Arc::new(Mutex::new(resolved_addrs)),
+            session_id,
+            gamer_id,
+        })
+    }
+
+    pub fn with_config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Sends the operation, retrying idempotent operations with exponential backoff when an
+    /// attempt fails or times out.
+    fn send_message_to_server(&self, op: Operation) -> Result<Response, Error> {
+        let op_encoded = one_shot::encode_request(&op, None)?;
+        let max_attempts = if op.is_idempotent() {
+            self.config.max_retries + 1
+        } else {
+            1
+        };
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            match self.exchange(&op_encoded) {
+                Err(err) if attempt < max_attempts => {
+                    warn!(
+                        "Attempt {}/{} failed: {}, retrying in {:?}",
+                        attempt, max_attempts, err, backoff
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2).min(self.config.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Box<dyn ServerStream>, Error> {
+        #[cfg(unix)]
+        if let ServerAddress::Unix(path) = &self.server_address {
+            return match UnixStream::connect(path) {
+                Ok(stream) => Ok(Box::new(stream)),
+                Err(err) => {
+                    error!("Failed to connect to {:?}: {:?}", path, err);
+                    Err(err.into())
+                }
+            };
+        }
+
+        Ok(Box::new(self.connect_tcp()?))
+    }
+
+    /// Tries every known server address in order. When all of them fail and re-resolving is
+    /// enabled, looks the hostname up again and tries the fresh addresses.
+    fn connect_tcp(&self) -> Result<TcpStream, Error> {
+        let addrs = self
+            .resolved_addrs
+            .lock()
+            .map_err(|_| "Resolved addresses lock is poisoned")?
+            .clone();
+
+        let err = match self.connect_any(&addrs) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => err,
+        };
+
+        if !self.config.re_resolve || !matches!(self.server_address, ServerAddress::Host(_)) {
+            return Err(err);
+        }
+
+        let fresh_addrs = self.server_address.resolve()?;
+        if fresh_addrs == addrs {
+            return Err(err);
+        }
+
+        info!(
+            "Server {:?} resolved to new addresses: {:?}",
+            self.server_address, fresh_addrs
+        );
+        *self
+            .resolved_addrs
+            .lock()
+            .map_err(|_| "Resolved addresses lock is poisoned")? = fresh_addrs.clone();
+
+        self.connect_any(&fresh_addrs)
+    }
+
+    /// Every address gets an equal share of the call timeout, so an unreachable one does not
+    /// keep the others from being tried.
+    fn connect_any(&self, addrs: &[SocketAddr]) -> Result<TcpStream, Error> {
+        let mut last_err: Option<std::io::Error> = None;
+        let connect_timeout = self.config.timeout / addrs.len().max(1) as u32;
+
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    error!("Failed to connect to {:?}: {:?}", addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err
+            .map(Error::from)
+            .unwrap_or_else(|| "No socket addresses to connect to".into()))
+    }
+
+    fn exchange(&self, op_encoded: &[u8]) -> Result<Response, Error> {
+        let mut stream = self.connect()?;
+        stream.set_timeouts(self.config.timeout)?;
+
+        if let Err(err) = stream.write_all(op_encoded) {
+            error!("Failed writing request: {:?}", err);
+            return Err(err.into());
+        }
+        stream.shutdown_write()?;
+
+        let mut response_bytes = vec![];
+        if let Err(err) = stream.read_to_end(&mut response_bytes) {
+            return match err.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    Err(ClientError::Timeout(self.config.timeout).into())
+                }
+                _ => Err(err.into()),
+            };
+        }
+
+        one_shot::decode_response(&response_bytes)
+    }
+
+    /// Access to the untyped API returning the server `Response` as is.
+    pub fn raw(&self) -> RawClientBlocking<'_> {
+        RawClientBlocking { client: self }
+    }
+
+    pub fn join_session(&self) -> Result<(), Error> {
+        expect_ok(self.raw().join_session()?)
+    }
+
+    pub fn reset_session(&self) -> Result<(), Error> {
+        expect_ok(self.raw().reset_session()?)
+    }
+
+    pub fn start_session(&self) -> Result<(), Error> {
+        expect_ok(self.raw().start_session()?)
+    }
+
+    pub fn end_session(&self) -> Result<(), Error> {
+        expect_ok(self.raw().end_session()?)
+    }
+
+    pub fn is_gamer_turn(&self) -> Result<bool, Error> {
+        expect_bool(self.raw().is_gamer_turn()?)
+    }
+
+    pub fn is_game_on(&self) -> Result<bool, Error> {
+        expect_bool(self.raw().is_game_on()?)
+    }
+
+    pub fn send_update(&self, update: Vec<u8>) -> Result<(), Error> {
+        expect_ok(self.raw().send_update(update)?)
+    }
+
+    pub fn get_previous_round_updates(
+        &self,
+    ) -> Result<HashMap<GamerIdType, Option<Vec<u8>>>, Error> {
+        expect_previous_round_updates(self.raw().get_previous_round_updates()?)
+    }
+
+    pub fn send_message(&self, message: Message) -> Result<(), Error> {
+        expect_ok(self.raw().send_message(message)?)
+    }
+
+    pub fn fetch_all_messages(&self) -> Result<Vec<Message>, Error> {
+        expect_messages(self.raw().fetch_all_messages()?)
+    }
+
+    /// Acknowledges every fetched message up to and including `seq`, removing them from the
+    /// server side queue. Unacknowledged messages are returned again by the next fetch.
+    pub fn ack_messages(&self, seq: u64) -> Result<(), Error> {
+        expect_ok(self.raw().ack_messages(seq)?)
+    }
+
+    pub fn assign_team(&self, team: TeamIdType) -> Result<(), Error> {
+        expect_ok(self.raw().assign_team(team)?)
+    }
+
+    pub fn leave_team(&self) -> Result<(), Error> {
+        expect_ok(self.raw().leave_team()?)
+    }
+
+    pub fn get_teams(&self) -> Result<HashMap<TeamIdType, Vec<GamerIdType>>, Error> {
+        expect_teams(self.raw().get_teams()?)
+    }
+
+    pub fn next_gamer(&self) -> Result<(), Error> {
+        expect_ok(self.raw().next_gamer()?)
+    }
+}
+
+/// Untyped view of `MGNClientBlocking`, every call returns the server `Response` unchanged.
+pub struct RawClientBlocking<'a> {
+    client: &'a MGNClientBlocking,
+}
+
+impl RawClientBlocking<'_> {
+    pub fn join_session(&self) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::JoinSession(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+        ))
+    }
+
+    pub fn reset_session(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::ResetSession(self.client.session_id.clone()))
+    }
+
+    pub fn start_session(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::StartSession(self.client.session_id.clone()))
+    }
+
+    pub fn end_session(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::EndSession(self.client.session_id.clone()))
+    }
+
+    pub fn is_gamer_turn(&self) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::IsGamerTurn(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+        ))
+    }
+
+    pub fn is_game_on(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::IsGameOn(self.client.session_id.clone()))
+    }
+
+    pub fn send_update(&self, update: Vec<u8>) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::SendUpdate(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+            update,
+        ))
+    }
+
+    pub fn get_previous_round_updates(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::GetPreviousRoundUpdates(
+                self.client.session_id.clone(),
+            ))
+    }
+
+    pub fn send_message(&self, message: Message) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::SendMessage(
+            self.client.session_id.clone(),
+            message,
+        ))
+    }
+
+    pub fn fetch_all_messages(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::FetchAllMessages(
+                self.client.session_id.clone(),
+                self.client.gamer_id.clone(),
+            ))
+    }
+
+    pub fn ack_messages(&self, seq: u64) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::AckMessages(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+            seq,
+        ))
+    }
+
+    pub fn assign_team(&self, team: TeamIdType) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::AssignTeam(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+            team,
+        ))
+    }
+
+    pub fn leave_team(&self) -> Result<Response, Error> {
+        self.client.send_message_to_server(Operation::LeaveTeam(
+            self.client.session_id.clone(),
+            self.client.gamer_id.clone(),
+        ))
+    }
+
+    pub fn get_teams(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::GetTeams(self.client.session_id.clone()))
+    }
+
+    pub fn next_gamer(&self) -> Result<Response, Error> {
+        self.client
+            .send_message_to_server(Operation::NextGamer(self.client.session_id.clone()))
+    }
+}
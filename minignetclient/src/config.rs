@@ -0,0 +1,94 @@
+#[cfg(any(feature = "native", feature = "blocking"))]
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{net::SocketAddr, time::Duration};
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Deadline for a single attempt of a call, including connecting.
+    pub timeout: Duration,
+    /// How many times an idempotent call is retried after a failed attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled after every further failed attempt.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Resolve the server hostname again when none of the known addresses accept a connection.
+    pub re_resolve: bool,
+    /// Connections kept open and shared by all clones of a client, concurrent calls are
+    /// multiplexed over them. 0 opens a new connection for every call.
+    pub pool_size: usize,
+    /// Requests of at least this many bytes are compressed on framed connections, when the
+    /// `zstd` or `lz4` feature is enabled and the server supports it.
+    pub compression_threshold: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            re_resolve: false,
+            pool_size: 4,
+            compression_threshold: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerAddress {
+    /// Fixed socket addresses, tried in order.
+    Resolved(Vec<SocketAddr>),
+    /// A `host:port` pair, resolved when the client is created and again on reconnect when
+    /// `ClientConfig::re_resolve` is set.
+    Host(String),
+    /// Path of a Unix domain socket, given as `unix:<path>`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ServerAddress {
+    #[cfg(any(feature = "native", feature = "blocking"))]
+    pub(crate) fn resolve(&self) -> Result<Vec<SocketAddr>, std::io::Error> {
+        let addrs: Vec<SocketAddr> = match self {
+            ServerAddress::Resolved(addrs) => addrs.clone(),
+            ServerAddress::Host(host) => host.to_socket_addrs()?.collect(),
+            #[cfg(unix)]
+            ServerAddress::Unix(_) => return Ok(vec![]),
+        };
+
+        if addrs.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No socket addresses found",
+            ));
+        }
+
+        Ok(addrs)
+    }
+}
+
+impl From<&str> for ServerAddress {
+    fn from(host: &str) -> Self {
+        ServerAddress::from(host.to_string())
+    }
+}
+
+impl From<String> for ServerAddress {
+    fn from(host: String) -> Self {
+        #[cfg(unix)]
+        if let Some(path) = host.strip_prefix("unix:") {
+            return ServerAddress::Unix(PathBuf::from(path));
+        }
+
+        ServerAddress::Host(host)
+    }
+}
+
+impl From<SocketAddr> for ServerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        ServerAddress::Resolved(vec![addr])
+    }
+}
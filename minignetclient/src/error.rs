@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use log::{error, trace};
+use minignetcommon::{
+    Error, ErrorReason, GamerIdType, Hello, HelloResponse, MIN_PROTOCOL_VERSION, Message,
+    PROTOCOL_VERSION, Response, TeamIdType,
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server did not answer within the configured timeout.
+    Timeout(Duration),
+    /// The server answered with `Response::Error`.
+    ServerError,
+    /// The server refused the operation for the given reason.
+    Rejected(ErrorReason),
+    /// The server answered with a response that does not fit the operation.
+    UnexpectedResponse(Response),
+}
+
+impl ClientError {
+    pub(crate) fn from_response(response: Response) -> Self {
+        match response {
+            Response::Error => ClientError::ServerError,
+            Response::ErrorWithReason(reason) => ClientError::Rejected(reason),
+            response => ClientError::UnexpectedResponse(response),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Timeout(duration) => write!(f, "Request timed out after {:?}", duration),
+            ClientError::ServerError => write!(f, "Server failed processing the request"),
+            ClientError::Rejected(reason) => write!(f, "Server rejected the request: {:?}", reason),
+            ClientError::UnexpectedResponse(response) => {
+                write!(f, "Unexpected response: {:?}", response)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+pub(crate) fn expect_ok(response: Response) -> Result<(), Error> {
+    match response {
+        Response::Ok => Ok(()),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+pub(crate) fn expect_bool(response: Response) -> Result<bool, Error> {
+    match response {
+        Response::OkWithBool(value) => Ok(value),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+pub(crate) fn expect_previous_round_updates(
+    response: Response,
+) -> Result<HashMap<GamerIdType, Option<Vec<u8>>>, Error> {
+    match response {
+        Response::OkWithPreviousRoundUpdates(updates) => Ok(updates),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+pub(crate) fn expect_messages(response: Response) -> Result<Vec<Message>, Error> {
+    match response {
+        Response::OkWithMessages(messages) => Ok(messages),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+pub(crate) fn expect_teams(
+    response: Response,
+) -> Result<HashMap<TeamIdType, Vec<GamerIdType>>, Error> {
+    match response {
+        Response::OkWithTeams(teams) => Ok(teams),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+#[cfg(feature = "native")]
+pub(crate) fn expect_token(response: Response) -> Result<u64, Error> {
+    match response {
+        Response::OkWithToken(token) => Ok(token),
+        response => Err(ClientError::from_response(response).into()),
+    }
+}
+
+/// Checks the server's answer to the `Hello` opening a framed connection.
+pub(crate) fn expect_hello(response: HelloResponse) -> Result<Hello, Error> {
+    match response {
+        HelloResponse::Accepted(hello) if hello.is_compatible() => {
+            trace!(
+                "Server speaks protocol version {}, capabilities: {:?}",
+                hello.protocol_version, hello.capabilities
+            );
+            Ok(hello)
+        }
+        HelloResponse::Accepted(Hello {
+            protocol_version, ..
+        }) => {
+            error!(
+                "Server protocol version {} is older than {}",
+                protocol_version, MIN_PROTOCOL_VERSION
+            );
+            Err(ClientError::Rejected(ErrorReason::IncompatibleVersion).into())
+        }
+        HelloResponse::Rejected {
+            protocol_version,
+            min_protocol_version,
+        } => {
+            error!(
+                "Server speaks protocol versions {} to {}, client speaks {}",
+                min_protocol_version, protocol_version, PROTOCOL_VERSION
+            );
+            Err(ClientError::Rejected(ErrorReason::IncompatibleVersion).into())
+        }
+    }
+}
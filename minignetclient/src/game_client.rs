@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use minignetcommon::{Error, GamerIdType, Message, Operation, Response, SessionIdType, TeamIdType};
+
+use crate::error::{
+    expect_bool, expect_messages, expect_ok, expect_previous_round_updates, expect_teams,
+};
+
+/// Operations of a game client. Implementors only provide `request`, the typed calls are built
+/// on top of it. `MGNClient` talks to a server, `MockClient` to an in-memory session.
+#[allow(async_fn_in_trait)]
+pub trait GameClient {
+    fn session_id(&self) -> &SessionIdType;
+
+    fn gamer_id(&self) -> &GamerIdType;
+
+    /// Sends the operation and returns the server `Response` as is.
+    async fn request(&self, op: Operation) -> Result<Response, Error>;
+
+    /// Access to the untyped API returning the server `Response` as is.
+    fn raw(&self) -> RawClient<'_, Self>
+    where
+        Self: Sized,
+    {
+        RawClient { client: self }
+    }
+
+    async fn join_session(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().join_session().await?)
+    }
+
+    async fn reset_session(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().reset_session().await?)
+    }
+
+    async fn start_session(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().start_session().await?)
+    }
+
+    async fn end_session(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().end_session().await?)
+    }
+
+    async fn is_gamer_turn(&self) -> Result<bool, Error>
+    where
+        Self: Sized,
+    {
+        expect_bool(self.raw().is_gamer_turn().await?)
+    }
+
+    async fn is_game_on(&self) -> Result<bool, Error>
+    where
+        Self: Sized,
+    {
+        expect_bool(self.raw().is_game_on().await?)
+    }
+
+    async fn send_update(&self, update: Vec<u8>) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().send_update(update).await?)
+    }
+
+    async fn get_previous_round_updates(
+        &self,
+    ) -> Result<HashMap<GamerIdType, Option<Vec<u8>>>, Error>
+    where
+        Self: Sized,
+    {
+        expect_previous_round_updates(self.raw().get_previous_round_updates().await?)
+    }
+
+    async fn send_message(&self, message: Message) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().send_message(message).await?)
+    }
+
+    /// The oldest unacknowledged messages, up to the server's `--max-fetch-size` payload bytes.
+    /// Acknowledge them to fetch the next ones.
+    async fn fetch_all_messages(&self) -> Result<Vec<Message>, Error>
+    where
+        Self: Sized,
+    {
+        expect_messages(self.raw().fetch_all_messages().await?)
+    }
+
+    /// Acknowledges every fetched message up to and including `seq`, removing them from the
+    /// server side queue. Unacknowledged messages are returned again by the next fetch.
+    async fn ack_messages(&self, seq: u64) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().ack_messages(seq).await?)
+    }
+
+    async fn assign_team(&self, team: TeamIdType) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().assign_team(team).await?)
+    }
+
+    async fn leave_team(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().leave_team().await?)
+    }
+
+    async fn get_teams(&self) -> Result<HashMap<TeamIdType, Vec<GamerIdType>>, Error>
+    where
+        Self: Sized,
+    {
+        expect_teams(self.raw().get_teams().await?)
+    }
+
+    async fn next_gamer(&self) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        expect_ok(self.raw().next_gamer().await?)
+    }
+}
+
+/// Untyped view of a `GameClient`, every call returns the server `Response` unchanged.
+pub struct RawClient<'a, C> {
+    client: &'a C,
+}
+
+impl<C: GameClient> RawClient<'_, C> {
+    pub async fn join_session(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::JoinSession(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+            ))
+            .await
+    }
+
+    pub async fn reset_session(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::ResetSession(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn start_session(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::StartSession(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn end_session(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::EndSession(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn is_gamer_turn(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::IsGamerTurn(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+            ))
+            .await
+    }
+
+    pub async fn is_game_on(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::IsGameOn(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn send_update(&self, update: Vec<u8>) -> Result<Response, Error> {
+        self.client
+            .request(Operation::SendUpdate(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+                update,
+            ))
+            .await
+    }
+
+    pub async fn get_previous_round_updates(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::GetPreviousRoundUpdates(
+                self.client.session_id().clone(),
+            ))
+            .await
+    }
+
+    pub async fn send_message(&self, message: Message) -> Result<Response, Error> {
+        self.client
+            .request(Operation::SendMessage(
+                self.client.session_id().clone(),
+                message,
+            ))
+            .await
+    }
+
+    pub async fn fetch_all_messages(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::FetchAllMessages(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+            ))
+            .await
+    }
+
+    pub async fn ack_messages(&self, seq: u64) -> Result<Response, Error> {
+        self.client
+            .request(Operation::AckMessages(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+                seq,
+            ))
+            .await
+    }
+
+    pub async fn assign_team(&self, team: TeamIdType) -> Result<Response, Error> {
+        self.client
+            .request(Operation::AssignTeam(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+                team,
+            ))
+            .await
+    }
+
+    pub async fn leave_team(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::LeaveTeam(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+            ))
+            .await
+    }
+
+    pub async fn get_teams(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::GetTeams(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn next_gamer(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::NextGamer(self.client.session_id().clone()))
+            .await
+    }
+
+    pub async fn get_channel_token(&self) -> Result<Response, Error> {
+        self.client
+            .request(Operation::GetChannelToken(
+                self.client.session_id().clone(),
+                self.client.gamer_id().clone(),
+            ))
+            .await
+    }
+}
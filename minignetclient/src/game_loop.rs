@@ -0,0 +1,310 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::info;
+use minignetcommon::{Error, GamerIdType, Message};
+
+use crate::{GameClient, MGNClient, time::sleep};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamePhase {
+    /// Joined, waiting for the session to start.
+    Waiting,
+    MyTurn,
+    OtherTurn,
+    Over,
+}
+
+/// Game logic driven by `GameLoopDriver`. Every callback has a no-op default, so a game only
+/// implements the events it cares about.
+#[allow(async_fn_in_trait)]
+pub trait GameLoop<C: GameClient = MGNClient> {
+    async fn on_session_start(&mut self, _client: &C) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn on_my_turn(&mut self, _client: &C) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn on_other_turn(&mut self, _client: &C) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once for every message addressed to this gamer. A message is acknowledged after its
+    /// callback succeeded, so a failing callback gets it and the ones after it delivered again.
+    async fn on_message(&mut self, _client: &C, _message: Message) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when another gamer's latest update changed since the previous poll.
+    async fn on_update(
+        &mut self,
+        _client: &C,
+        _gamer_id: GamerIdType,
+        _update: Vec<u8>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn on_game_over(&mut self, _client: &C) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Polls the server and turns session changes, messages and updates into `GameLoop` callbacks.
+pub struct GameLoopDriver<C: GameClient = MGNClient> {
+    client: C,
+    poll_interval: Duration,
+    poll_updates: bool,
+    phase: GamePhase,
+    seen_updates: HashMap<GamerIdType, Vec<u8>>,
+    /// Sequence number of the last message handled, skipped when a failed ack gets it fetched
+    /// again.
+    handled_seq: u64,
+}
+
+impl<C: GameClient> GameLoopDriver<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            poll_interval: Duration::from_millis(500),
+            poll_updates: true,
+            phase: GamePhase::Waiting,
+            seen_updates: HashMap::new(),
+            handled_seq: 0,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Games that do not use `send_update` can skip polling for updates.
+    pub fn with_update_polling(mut self, poll_updates: bool) -> Self {
+        self.poll_updates = poll_updates;
+        self
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        self.phase
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Polls until the game is over.
+    pub async fn run<G: GameLoop<C>>(&mut self, game: &mut G) -> Result<(), Error> {
+        loop {
+            self.step(game).await?;
+
+            if self.phase == GamePhase::Over {
+                return Ok(());
+            }
+
+            sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Polls the server once and dispatches whatever changed. Games that also wait on other
+    /// events (eg. user input) call this on their own schedule instead of `run`.
+    pub async fn step<G: GameLoop<C>>(&mut self, game: &mut G) -> Result<(), Error> {
+        self.dispatch_messages(game).await?;
+
+        if self.poll_updates {
+            self.dispatch_updates(game).await?;
+        }
+
+        self.dispatch_phase_change(game).await
+    }
+
+    async fn dispatch_messages<G: GameLoop<C>>(&mut self, game: &mut G) -> Result<(), Error> {
+        let messages = self.client.fetch_all_messages().await?;
+        let Some(first_seq) = messages.first().map(|message| message.seq) else {
+            return Ok(());
+        };
+
+        let mut result = Ok(());
+        for message in messages {
+            if message.seq <= self.handled_seq {
+                continue;
+            }
+
+            let seq = message.seq;
+            if let Err(err) = game.on_message(&self.client, message).await {
+                result = Err(err);
+                break;
+            }
+            self.handled_seq = seq;
+        }
+
+        // Acknowledges the handled messages even when a later one failed.
+        if first_seq <= self.handled_seq {
+            self.client.ack_messages(self.handled_seq).await?;
+        }
+
+        result
+    }
+
+    async fn dispatch_updates<G: GameLoop<C>>(&mut self, game: &mut G) -> Result<(), Error> {
+        for (gamer_id, update) in self.client.get_previous_round_updates().await? {
+            let Some(update) = update else {
+                continue;
+            };
+            if &gamer_id == self.client.gamer_id()
+                || self.seen_updates.get(&gamer_id) == Some(&update)
+            {
+                continue;
+            }
+
+            self.seen_updates.insert(gamer_id.clone(), update.clone());
+            game.on_update(&self.client, gamer_id, update).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn dispatch_phase_change<G: GameLoop<C>>(&mut self, game: &mut G) -> Result<(), Error> {
+        let is_game_on = self.client.is_game_on().await?;
+
+        match (self.phase, is_game_on) {
+            (GamePhase::Waiting | GamePhase::Over, false) => return Ok(()),
+            (GamePhase::Waiting | GamePhase::Over, true) => {
+                info!("Game session has started");
+                self.phase = GamePhase::Waiting;
+                self.seen_updates.clear();
+                game.on_session_start(&self.client).await?;
+            }
+            (GamePhase::MyTurn | GamePhase::OtherTurn, false) => {
+                info!("Game session is over");
+                self.phase = GamePhase::Over;
+                return game.on_game_over(&self.client).await;
+            }
+            (GamePhase::MyTurn | GamePhase::OtherTurn, true) => {}
+        }
+
+        let phase = if self.client.is_gamer_turn().await? {
+            GamePhase::MyTurn
+        } else {
+            GamePhase::OtherTurn
+        };
+        if phase == self.phase {
+            return Ok(());
+        }
+
+        info!("Game phase change: {:?} -> {:?}", self.phase, phase);
+        self.phase = phase;
+
+        match phase {
+            GamePhase::MyTurn => game.on_my_turn(&self.client).await,
+            _ => game.on_other_turn(&self.client).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use minignetcommon::{MessageAddress, Operation, Response, SessionIdType};
+
+    use super::*;
+    use crate::{MockClient, MockServer};
+
+    /// Fails the next `AckMessages` when asked to, like a connection dropping.
+    struct FlakyAcks {
+        client: MockClient,
+        fail_next_ack: Cell<bool>,
+    }
+
+    impl GameClient for FlakyAcks {
+        fn session_id(&self) -> &SessionIdType {
+            self.client.session_id()
+        }
+
+        fn gamer_id(&self) -> &GamerIdType {
+            self.client.gamer_id()
+        }
+
+        async fn request(&self, op: Operation) -> Result<Response, Error> {
+            if matches!(op, Operation::AckMessages(..)) && self.fail_next_ack.take() {
+                return Err("Connection closed".into());
+            }
+            self.client.request(op).await
+        }
+    }
+
+    /// Records the payloads it handled and fails once on the payload in `fail_on`.
+    #[derive(Default)]
+    struct Recorder {
+        handled: Vec<u8>,
+        fail_on: Option<u8>,
+    }
+
+    impl<C: GameClient> GameLoop<C> for Recorder {
+        async fn on_message(&mut self, _client: &C, message: Message) -> Result<(), Error> {
+            let payload = message.payload[0];
+            if self.fail_on == Some(payload) {
+                self.fail_on = None;
+                return Err("Callback failed".into());
+            }
+            self.handled.push(payload);
+            Ok(())
+        }
+    }
+
+    async fn session_with_messages(payloads: &[u8]) -> (MockServer, MockClient) {
+        let server = MockServer::default();
+        let alice = server.client("game".to_string(), "alice".to_string());
+        let bob = server.client("game".to_string(), "bob".to_string());
+        alice.join_session().await.unwrap();
+        bob.join_session().await.unwrap();
+
+        for payload in payloads {
+            let message = Message::new("alice".to_string(), MessageAddress::All, vec![*payload]);
+            alice.send_message(message).await.unwrap();
+        }
+
+        (server, bob)
+    }
+
+    #[tokio::test]
+    async fn failing_callback_gets_only_the_unhandled_messages_again() {
+        let (_server, bob) = session_with_messages(&[1, 2, 3]).await;
+        let mut driver = GameLoopDriver::new(bob.clone());
+        let mut game = Recorder {
+            fail_on: Some(2),
+            ..Recorder::default()
+        };
+
+        assert!(driver.step(&mut game).await.is_err());
+        assert_eq!(game.handled, [1]);
+        assert_eq!(bob.fetch_all_messages().await.unwrap().len(), 2);
+
+        driver.step(&mut game).await.unwrap();
+        assert_eq!(game.handled, [1, 2, 3]);
+        assert!(bob.fetch_all_messages().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_ack_does_not_deliver_messages_twice() {
+        let (_server, bob) = session_with_messages(&[1, 2]).await;
+        let mut driver = GameLoopDriver::new(FlakyAcks {
+            client: bob.clone(),
+            fail_next_ack: Cell::new(true),
+        });
+        let mut game = Recorder::default();
+
+        assert!(driver.step(&mut game).await.is_err());
+        assert_eq!(game.handled, [1, 2]);
+
+        driver.step(&mut game).await.unwrap();
+        assert_eq!(game.handled, [1, 2]);
+        assert!(bob.fetch_all_messages().await.unwrap().is_empty());
+    }
+}
@@ -1,137 +1,58 @@
 extern crate log;
 
-use std::net::{SocketAddr, ToSocketAddrs};
-
-use log::error;
-use minignetcommon::{
-    Error, GamerIdType, Message, Operation, Response, SessionIdType, read_socket_till_end,
-};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
-
-#[derive(Clone)]
-pub struct MGNClient {
-    serialization_config: bincode::config::Configuration,
-    addr: SocketAddr,
-    pub session_id: SessionIdType,
-    pub gamer_id: GamerIdType,
-}
-
-impl MGNClient {
-    pub fn new<Addr>(
-        addr: Addr,
-        session_id: SessionIdType,
-        gamer_id: GamerIdType,
-    ) -> Result<Self, std::io::Error>
-    where
-        Addr: ToSocketAddrs,
-    {
-        let mut address_options = addr.to_socket_addrs().expect("msg");
-        let first_address = address_options.next().ok_or(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "No socket addresses found",
-        ))?;
-
-        Ok(Self {
-            serialization_config: bincode::config::standard(),
-            addr: first_address,
-            session_id,
-            gamer_id,
-        })
-    }
-
-    async fn send_message_to_server(&self, op: Operation) -> Result<Response, Error> {
-        let op_encoded = bincode::encode_to_vec(op, self.serialization_config)?;
-
-        match TcpStream::connect(self.addr).await {
-            Ok(mut stream) => {
-                let (mut reader, mut writer) = stream.split();
-                if let Err(err) = writer.write_all(&op_encoded[..]).await {
-                    error!("Failed writing request: {:?}", err);
-                    return Err(err.into());
-                }
-                writer
-                    .shutdown()
-                    .await
-                    .expect("Failed shutting down writer");
-
-                let response_bytes = read_socket_till_end(&mut reader).await?;
-                let (decoded, _size): (Response, usize) =
-                    bincode::decode_from_slice(&response_bytes[..], self.serialization_config)?;
-
-                return Ok(decoded);
-            }
-            Err(e) => {
-                eprintln!("Failed to connect: {} to: {:?}", e, self.addr);
-                return Err(e.into());
-            }
-        }
-    }
-
-    pub async fn join_session(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::JoinSession(
-            self.session_id.clone(),
-            self.gamer_id.clone(),
-        ))
-        .await
-    }
-
-    pub async fn reset_session(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::ResetSession(self.session_id.clone()))
-            .await
-    }
-
-    pub async fn start_session(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::StartSession(self.session_id.clone()))
-            .await
-    }
-
-    pub async fn end_session(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::EndSession(self.session_id.clone()))
-            .await
-    }
-
-    pub async fn is_gamer_turn(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::IsGamerTurn(
-            self.session_id.clone(),
-            self.gamer_id.clone(),
-        ))
-        .await
-    }
-
-    pub async fn is_game_on(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::IsGameOn(self.session_id.clone()))
-            .await
-    }
-
-    pub async fn send_update(&self, update: Vec<u8>) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::SendUpdate(
-            self.session_id.clone(),
-            self.gamer_id.clone(),
-            update,
-        ))
-        .await
-    }
-
-    pub async fn get_previous_round_updates(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::GetPreviousRoundUpdates(self.session_id.clone()))
-            .await
-    }
-
-    pub async fn send_message(&self, message: Message) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::SendMessage(self.session_id.clone(), message))
-            .await
-    }
-
-    pub async fn fetch_all_messages(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::FetchAllMessages(
-            self.session_id.clone(),
-            self.gamer_id.clone(),
-        ))
-        .await
-    }
-
-    pub async fn next_gamer(&self) -> Result<Response, Error> {
-        self.send_message_to_server(Operation::NextGamer(self.session_id.clone()))
-            .await
-    }
-}
+#[cfg(feature = "blocking")]
+mod blocking;
+mod config;
+mod error;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod game_client;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod game_loop;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod mock;
+#[cfg(feature = "native")]
+mod native;
+#[cfg(any(feature = "native", feature = "blocking"))]
+mod one_shot;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod pending;
+#[cfg(feature = "native")]
+mod pool;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod retry;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod time;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(any(feature = "native", feature = "wasm"))]
+mod typed;
+#[cfg(feature = "native")]
+mod udp;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "blocking")]
+pub use blocking::{MGNClientBlocking, RawClientBlocking};
+pub use config::{ClientConfig, ServerAddress};
+pub use error::ClientError;
+#[cfg(any(feature = "native", feature = "wasm"))]
+pub use game_client::{GameClient, RawClient};
+#[cfg(any(feature = "native", feature = "wasm"))]
+pub use game_loop::{GameLoop, GameLoopDriver, GamePhase};
+#[cfg(any(feature = "native", feature = "wasm"))]
+pub use mock::{MockClient, MockServer};
+#[cfg(feature = "native")]
+pub use native::MGNClient;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+#[cfg(any(feature = "native", feature = "wasm"))]
+pub use typed::TypedClient;
+#[cfg(feature = "native")]
+pub use udp::Snapshots;
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub use wasm::WebSocketClient as MGNClient;
+#[cfg(feature = "wasm")]
+pub use wasm::WebSocketClient;
+
+#[cfg(not(any(feature = "native", feature = "wasm", feature = "blocking")))]
+compile_error!("Enable the native, the wasm or the blocking feature of minignetclient");
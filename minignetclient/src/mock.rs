@@ -0,0 +1,155 @@
+use std::sync::{Arc, Mutex};
+
+use minignetcommon::{
+    Error, GamerIdType, MessageLimits, Operation, Response, SessionIdType, WorldState,
+};
+
+use crate::GameClient;
+
+/// In-memory stand-in for a server, running the same session logic. Clients created from one
+/// `MockServer` share its sessions, so several simulated players can be driven step by step
+/// from a single test without any networking.
+#[derive(Clone)]
+pub struct MockServer {
+    world: Arc<Mutex<WorldState>>,
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new(MessageLimits::default())
+    }
+}
+
+impl MockServer {
+    pub fn new(message_limits: MessageLimits) -> Self {
+        Self {
+            world: Arc::new(Mutex::new(WorldState::new(message_limits))),
+        }
+    }
+
+    pub fn client(&self, session_id: SessionIdType, gamer_id: GamerIdType) -> MockClient {
+        MockClient {
+            world: self.world.clone(),
+            session_id,
+            gamer_id,
+        }
+    }
+
+    /// Gives direct access to the sessions, eg. to assert on their state.
+    pub fn with_world<T>(&self, f: impl FnOnce(&mut WorldState) -> T) -> Result<T, Error> {
+        let mut world = self
+            .world
+            .lock()
+            .map_err(|_| "World state lock is poisoned")?;
+        Ok(f(&mut world))
+    }
+}
+
+#[derive(Clone)]
+pub struct MockClient {
+    world: Arc<Mutex<WorldState>>,
+    pub session_id: SessionIdType,
+    pub gamer_id: GamerIdType,
+}
+
+impl GameClient for MockClient {
+    fn session_id(&self) -> &SessionIdType {
+        &self.session_id
+    }
+
+    fn gamer_id(&self) -> &GamerIdType {
+        &self.gamer_id
+    }
+
+    async fn request(&self, op: Operation) -> Result<Response, Error> {
+        let mut world = self
+            .world
+            .lock()
+            .map_err(|_| "World state lock is poisoned")?;
+        Ok(world.handle(op))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minignetcommon::{Message, MessageAddress};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn two_players_take_turns_and_exchange_messages() {
+        let server = MockServer::default();
+        let alice = server.client("game".to_string(), "alice".to_string());
+        let bob = server.client("game".to_string(), "bob".to_string());
+
+        alice.join_session().await.unwrap();
+        bob.join_session().await.unwrap();
+        assert!(!alice.is_game_on().await.unwrap());
+
+        alice.start_session().await.unwrap();
+        assert!(bob.is_game_on().await.unwrap());
+        assert!(alice.is_gamer_turn().await.unwrap());
+        assert!(!bob.is_gamer_turn().await.unwrap());
+
+        let message = Message::new("alice".to_string(), MessageAddress::All, vec![1, 2]);
+        alice.send_message(message).await.unwrap();
+        assert!(alice.fetch_all_messages().await.unwrap().is_empty());
+
+        let messages = bob.fetch_all_messages().await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].from, "alice");
+        assert_eq!(messages[0].payload, [1, 2]);
+
+        // Fetching again without an ack returns the same message.
+        assert_eq!(bob.fetch_all_messages().await.unwrap().len(), 1);
+        bob.ack_messages(messages[0].seq).await.unwrap();
+        assert!(bob.fetch_all_messages().await.unwrap().is_empty());
+
+        alice.next_gamer().await.unwrap();
+        assert!(!alice.is_gamer_turn().await.unwrap());
+        assert!(bob.is_gamer_turn().await.unwrap());
+
+        bob.next_gamer().await.unwrap();
+        assert!(alice.is_gamer_turn().await.unwrap());
+
+        alice.end_session().await.unwrap();
+        assert!(!bob.is_game_on().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn updates_and_teams_are_shared() {
+        let server = MockServer::default();
+        let alice = server.client("game".to_string(), "alice".to_string());
+        let bob = server.client("game".to_string(), "bob".to_string());
+        alice.join_session().await.unwrap();
+        bob.join_session().await.unwrap();
+
+        alice.send_update(vec![1]).await.unwrap();
+        alice.send_update(vec![2]).await.unwrap();
+        let updates = bob.get_previous_round_updates().await.unwrap();
+        assert_eq!(updates["alice"], Some(vec![2]));
+        assert_eq!(updates["bob"], None);
+
+        alice.assign_team("red".to_string()).await.unwrap();
+        bob.assign_team("red".to_string()).await.unwrap();
+        assert_eq!(
+            alice.get_teams().await.unwrap()["red"],
+            ["alice".to_string(), "bob".to_string()]
+        );
+
+        bob.leave_team().await.unwrap();
+        assert_eq!(
+            alice.get_teams().await.unwrap()["red"],
+            ["alice".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_session_is_an_error() {
+        let server = MockServer::default();
+        let alice = server.client("game".to_string(), "alice".to_string());
+
+        assert!(alice.next_gamer().await.is_err());
+        assert!(alice.fetch_all_messages().await.is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use log::{error, info};
+use minignetcommon::{
+    Error, GamerIdType, Operation, Response, SessionIdType, read_socket_till_end,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs, lookup_host},
+    time::timeout,
+};
+#[cfg(feature = "tls")]
+use tokio_rustls::{client::TlsStream, rustls::pki_types::ServerName};
+
+#[cfg(feature = "tls")]
+use crate::TlsConfig;
+use crate::{
+    ClientConfig, GameClient, ServerAddress, Snapshots, error::expect_token, one_shot,
+    pool::ConnectionPool, retry::with_retries, udp::UnreliableChannel,
+};
+
+/// A connection to the server, over TCP or a Unix domain socket.
+pub(crate) trait ServerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ServerStream for T {}
+
+#[derive(Clone)]
+pub struct MGNClient {
+    config: ClientConfig,
+    server_address: ServerAddress,
+    resolved_addrs: Arc<Mutex<Vec<SocketAddr>>>,
+    pool: Option<Arc<ConnectionPool>>,
+    trace_id: Option<String>,
+    unreliable: Arc<OnceLock<UnreliableChannel>>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    pub session_id: SessionIdType,
+    pub gamer_id: GamerIdType,
+}
+
+impl MGNClient {
+    pub fn new<Addr>(
+        addr: Addr,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+    ) -> Result<Self, std::io::Error>
+    where
+        Addr: Into<ServerAddress>,
+    {
+        let server_address = addr.into();
+        let resolved_addrs = server_address.resolve()?;
+        let config = ClientConfig::default();
+
+        Ok(Self {
+            pool: Self::pool(&config),
+            config,
+            server_address,
+            resolved_addrs: Arc::new(Mutex::new(resolved_addrs)),
+            trace_id: None,
+            unreliable: Arc::new(OnceLock::new()),
+            #[cfg(feature = "tls")]
+            tls: None,
+            session_id,
+            gamer_id,
+        })
+    }
+
+    pub fn with_config(mut self, config: ClientConfig) -> Self {
+        self.pool = Self::pool(&config);
+        self.config = config;
+        self
+    }
+
+    /// Tags every call with `trace_id`, which the server includes in its logs, over pooled and
+    /// one-shot connections alike. Datagrams of the unreliable channel are not tagged.
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    /// Encrypts the TCP connections with TLS. Unix socket connections and the unreliable channel
+    /// stay in plaintext.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Opens the unreliable channel to the server's UDP socket at `addr`, shared by all clones
+    /// of the client. Returns the snapshots the other gamers of the session send with
+    /// `send_unreliable_update`. The gamer has to join the session first.
+    pub async fn open_unreliable_channel<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<Snapshots, Error> {
+        let token = expect_token(self.raw().get_channel_token().await?)?;
+        let (channel, snapshots) =
+            UnreliableChannel::open(addr, self.session_id.clone(), self.gamer_id.clone(), token)
+                .await?;
+        self.unreliable
+            .set(channel)
+            .map_err(|_| "Unreliable channel is already open")?;
+
+        Ok(snapshots)
+    }
+
+    /// Sends the gamer's latest state over the unreliable channel. It may be lost or overtaken
+    /// by a later update, so every update should carry the full state rather than a change.
+    pub async fn send_unreliable_update(&self, payload: Vec<u8>) -> Result<(), Error> {
+        self.unreliable
+            .get()
+            .ok_or("Unreliable channel is not open")?
+            .send(payload)
+            .await
+    }
+
+    fn pool(config: &ClientConfig) -> Option<Arc<ConnectionPool>> {
+        (config.pool_size > 0).then(|| {
+            Arc::new(ConnectionPool::new(
+                config.pool_size,
+                config.compression_threshold,
+            ))
+        })
+    }
+
+    /// Sends the operation, retrying idempotent operations with exponential backoff when an
+    /// attempt fails or times out.
+    async fn send_message_to_server(&self, op: Operation) -> Result<Response, Error> {
+        let op_encoded = one_shot::encode_request(&op, self.trace_id.as_deref())?;
+        with_retries(&self.config, &op, || self.exchange(&op, &op_encoded)).await
+    }
+
+    async fn connect(&self) -> Result<Box<dyn ServerStream>, Error> {
+        #[cfg(unix)]
+        if let ServerAddress::Unix(path) = &self.server_address {
+            return match UnixStream::connect(path).await {
+                Ok(stream) => Ok(Box::new(stream)),
+                Err(err) => {
+                    error!("Failed to connect to {:?}: {:?}", path, err);
+                    Err(err.into())
+                }
+            };
+        }
+
+        let stream = self.connect_tcp().await?;
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return Ok(Box::new(self.connect_tls(tls, stream).await?));
+        }
+
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(feature = "tls")]
+    async fn connect_tls(
+        &self,
+        tls: &TlsConfig,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, Error> {
+        let server_name = match (tls.server_name(), &self.server_address) {
+            (Some(server_name), _) => server_name.clone(),
+            (None, ServerAddress::Host(host)) => {
+                let name = host
+                    .rsplit_once(':')
+                    .map_or(host.as_str(), |(name, _)| name);
+                let name = name.trim_start_matches('[').trim_end_matches(']');
+                ServerName::try_from(name.to_string())?
+            }
+            (None, _) => ServerName::IpAddress(stream.peer_addr()?.ip().into()),
+        };
+
+        match tls.connector().connect(server_name, stream).await {
+            Ok(stream) => Ok(stream),
+            Err(err) => {
+                error!("TLS handshake failed: {:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Tries every known server address in order. When all of them fail and re-resolving is
+    /// enabled, looks the hostname up again and tries the fresh addresses.
+    async fn connect_tcp(&self) -> Result<TcpStream, Error> {
+        let addrs = self
+            .resolved_addrs
+            .lock()
+            .map_err(|_| "Resolved addresses lock is poisoned")?
+            .clone();
+
+        let err = match self.connect_any(&addrs).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => err,
+        };
+
+        let ServerAddress::Host(host) = &self.server_address else {
+            return Err(err);
+        };
+        if !self.config.re_resolve {
+            return Err(err);
+        }
+
+        let fresh_addrs: Vec<SocketAddr> = lookup_host(host.as_str()).await?.collect();
+        if fresh_addrs.is_empty() || fresh_addrs == addrs {
+            return Err(err);
+        }
+
+        info!(
+            "Server {} resolved to new addresses: {:?}",
+            host, fresh_addrs
+        );
+        *self
+            .resolved_addrs
+            .lock()
+            .map_err(|_| "Resolved addresses lock is poisoned")? = fresh_addrs.clone();
+
+        self.connect_any(&fresh_addrs).await
+    }
+
+    /// Every address gets an equal share of the call timeout, so an unreachable one does not
+    /// keep the others from being tried.
+    async fn connect_any(&self, addrs: &[SocketAddr]) -> Result<TcpStream, Error> {
+        let mut last_err: Option<std::io::Error> = None;
+        let connect_timeout = self.config.timeout / addrs.len().max(1) as u32;
+
+        for addr in addrs {
+            let connected = timeout(connect_timeout, TcpStream::connect(addr))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("Connecting timed out after {:?}", connect_timeout),
+                    ))
+                });
+            match connected {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    error!("Failed to connect to {:?}: {:?}", addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err
+            .map(Error::from)
+            .unwrap_or_else(|| "No socket addresses to connect to".into()))
+    }
+
+    async fn exchange(&self, op: &Operation, op_encoded: &[u8]) -> Result<Response, Error> {
+        match &self.pool {
+            Some(pool) => {
+                pool.request(op, self.trace_id.as_deref(), || self.connect())
+                    .await
+            }
+            None => self.exchange_once(op_encoded).await,
+        }
+    }
+
+    /// Sends the request over a new connection and reads the response until the server closes it.
+    async fn exchange_once(&self, op_encoded: &[u8]) -> Result<Response, Error> {
+        let stream = self.connect().await?;
+
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        if let Err(err) = writer.write_all(op_encoded).await {
+            error!("Failed writing request: {:?}", err);
+            return Err(err.into());
+        }
+        writer.shutdown().await?;
+
+        let response_bytes = read_socket_till_end(&mut reader).await?;
+
+        one_shot::decode_response(&response_bytes)
+    }
+}
+
+impl GameClient for MGNClient {
+    fn session_id(&self) -> &SessionIdType {
+        &self.session_id
+    }
+
+    fn gamer_id(&self) -> &GamerIdType {
+        &self.gamer_id
+    }
+
+    async fn request(&self, op: Operation) -> Result<Response, Error> {
+        self.send_message_to_server(op).await
+    }
+}
@@ -0,0 +1,43 @@
+use minignetcommon::{
+    Error, FRAMED_PREAMBLE, Hello, HelloResponse, Operation, RequestEnvelope, Response,
+    ResponseEnvelope, decode_untrusted, push_frame, split_frame,
+};
+
+use crate::error::expect_hello;
+
+/// Encodes a call made over its own connection: the preamble, a `Hello` offering no
+/// capabilities and a single request, written at once. The server checks the protocol version
+/// as on any framed connection.
+pub(crate) fn encode_request(
+    operation: &Operation,
+    trace_id: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    let hello = bincode::encode_to_vec(Hello::new(vec![]), bincode::config::standard())?;
+    let request = bincode::encode_to_vec(
+        RequestEnvelope {
+            id: 0,
+            trace_id: trace_id.map(str::to_string),
+            operation: operation.clone(),
+        },
+        bincode::config::standard(),
+    )?;
+
+    let mut bytes = FRAMED_PREAMBLE.to_vec();
+    push_frame(&mut bytes, &hello)?;
+    push_frame(&mut bytes, &request)?;
+
+    Ok(bytes)
+}
+
+/// Decodes what the server sent before closing the connection: its `HelloResponse`, then the
+/// response to the request.
+pub(crate) fn decode_response(bytes: &[u8]) -> Result<Response, Error> {
+    let (hello, rest) = split_frame(bytes)?;
+    let (hello, _size): (HelloResponse, usize) = decode_untrusted(hello)?;
+    expect_hello(hello)?;
+
+    let (response, _rest) = split_frame(rest)?;
+    let (envelope, _size): (ResponseEnvelope, usize) = decode_untrusted(response)?;
+
+    Ok(envelope.response)
+}
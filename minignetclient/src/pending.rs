@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use log::warn;
+use minignetcommon::{Error, Response, ResponseEnvelope};
+use tokio::sync::oneshot;
+
+/// Calls waiting for their response on a framed connection, matched by request id.
+pub(crate) struct PendingResponses {
+    /// `None` once the connection is closed.
+    senders: Mutex<Option<HashMap<u64, oneshot::Sender<Response>>>>,
+    next_request_id: AtomicU64,
+}
+
+impl PendingResponses {
+    pub(crate) fn new() -> Self {
+        Self {
+            senders: Mutex::new(Some(HashMap::new())),
+            next_request_id: AtomicU64::new(1),
+        }
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.senders
+            .lock()
+            .map(|senders| senders.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Registers a call under a fresh request id, fails when the connection is closed.
+    pub(crate) fn register(&self) -> Result<PendingCall<'_>, Error> {
+        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+
+        self.senders
+            .lock()
+            .map_err(|_| "Pending responses lock is poisoned")?
+            .as_mut()
+            .ok_or("Connection is closed")?
+            .insert(id, sender);
+
+        Ok(PendingCall {
+            pending: self,
+            id,
+            receiver,
+        })
+    }
+
+    /// Hands the response to the call waiting for it.
+    pub(crate) fn resolve(&self, envelope: ResponseEnvelope) {
+        let sender = self
+            .senders
+            .lock()
+            .ok()
+            .and_then(|mut senders| senders.as_mut()?.remove(&envelope.id));
+
+        match sender {
+            Some(sender) => {
+                let _ = sender.send(envelope.response);
+            }
+            None => warn!(
+                "Dropping response to unknown request #{} [{:?}]: {:?}",
+                envelope.id, envelope.trace_id, envelope.response
+            ),
+        }
+    }
+
+    /// Dropping the senders fails every call still waiting on this connection.
+    pub(crate) fn close(&self) {
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.take();
+        }
+    }
+}
+
+/// A registered call. Forgets its pending response when dropped, eg. after a timeout.
+pub(crate) struct PendingCall<'a> {
+    pending: &'a PendingResponses,
+    id: u64,
+    receiver: oneshot::Receiver<Response>,
+}
+
+impl PendingCall<'_> {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) async fn response(&mut self) -> Result<Response, Error> {
+        (&mut self.receiver)
+            .await
+            .map_err(|_| "Connection closed before the response arrived".into())
+    }
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut senders) = self.pending.senders.lock()
+            && let Some(senders) = senders.as_mut()
+        {
+            senders.remove(&self.id);
+        }
+    }
+}
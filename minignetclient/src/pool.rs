@@ -0,0 +1,302 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use log::{error, trace, warn};
+use minignetcommon::{
+    Compression, Error, FRAMED_PREAMBLE, FrameCodec, Hello, HelloResponse, Operation,
+    RequestEnvelope, Response, decode_untrusted, read_frame, write_frame,
+};
+use tokio::{
+    io::{AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{Mutex, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{error::expect_hello, native::ServerStream, pending::PendingResponses};
+
+/// Largest response frame accepted from the server.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Frames queued for the writer task of a connection.
+const REQUEST_QUEUE_SIZE: usize = 64;
+
+/// A fixed number of framed connections, opened lazily and reopened after they break. Calls are
+/// spread over them round robin and matched to their responses by request id.
+pub(crate) struct ConnectionPool {
+    slots: Vec<Mutex<Option<Arc<Connection>>>>,
+    next_slot: AtomicUsize,
+    compression_threshold: usize,
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(size: usize, compression_threshold: usize) -> Self {
+        Self {
+            slots: (0..size).map(|_| Mutex::new(None)).collect(),
+            next_slot: AtomicUsize::new(0),
+            compression_threshold,
+        }
+    }
+
+    /// Sends the operation over a pooled connection, calling `connect` when the chosen slot has
+    /// no open connection.
+    pub(crate) async fn request<F, Fut>(
+        &self,
+        operation: &Operation,
+        trace_id: Option<&str>,
+        connect: F,
+    ) -> Result<Response, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Box<dyn ServerStream>, Error>>,
+    {
+        let connection = self.connection(connect).await?;
+        connection.request(operation, trace_id).await
+    }
+
+    async fn connection<F, Fut>(&self, connect: F) -> Result<Arc<Connection>, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Box<dyn ServerStream>, Error>>,
+    {
+        let index = self.next_slot.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut slot = self.slots[index].lock().await;
+
+        if let Some(connection) = slot.as_ref().filter(|connection| connection.is_open()) {
+            return Ok(connection.clone());
+        }
+
+        trace!("Opening pooled connection #{}", index);
+        let connection =
+            Arc::new(Connection::open(connect().await?, self.compression_threshold).await?);
+        *slot = Some(connection.clone());
+
+        Ok(connection)
+    }
+}
+
+struct Connection {
+    requests: mpsc::Sender<Vec<u8>>,
+    pending: Arc<PendingResponses>,
+    codec: FrameCodec,
+    reader: JoinHandle<()>,
+}
+
+/// Closes the connection when a call is given up while waiting for its response, eg. after the
+/// call timeout. A server that silently disappeared never closes it, so every later call on
+/// the slot would time out too instead of reconnecting.
+struct CloseIfAbandoned<'a> {
+    connection: &'a Connection,
+    waiting: bool,
+}
+
+impl Drop for CloseIfAbandoned<'_> {
+    fn drop(&mut self) {
+        if self.waiting {
+            warn!("Closing pooled connection after a call was abandoned");
+            self.connection.close();
+        }
+    }
+}
+
+impl Connection {
+    async fn open(
+        mut stream: Box<dyn ServerStream>,
+        compression_threshold: usize,
+    ) -> Result<Self, Error> {
+        stream.write_all(&FRAMED_PREAMBLE).await?;
+        let hello = Connection::handshake(&mut stream).await?;
+        let codec = FrameCodec::new(
+            Compression::negotiate(&hello.capabilities),
+            compression_threshold,
+        );
+
+        let (reader, writer) = tokio::io::split(stream);
+        let pending = Arc::new(PendingResponses::new());
+        let (requests, request_receiver) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        tokio::spawn(Connection::write_requests(
+            writer,
+            request_receiver,
+            pending.clone(),
+        ));
+        let reader = tokio::spawn(Connection::read_responses(reader, pending.clone(), codec));
+
+        Ok(Self {
+            requests,
+            pending,
+            codec,
+            reader,
+        })
+    }
+
+    async fn handshake(stream: &mut Box<dyn ServerStream>) -> Result<Hello, Error> {
+        let hello = bincode::encode_to_vec(
+            Hello::new(Compression::capabilities()),
+            bincode::config::standard(),
+        )?;
+        write_frame(stream, &hello).await?;
+
+        let frame = read_frame(stream, MAX_RESPONSE_SIZE)
+            .await?
+            .ok_or("Connection closed during handshake")?;
+        let (response, _size): (HelloResponse, usize) = decode_untrusted(&frame[..])?;
+
+        expect_hello(response)
+    }
+
+    fn is_open(&self) -> bool {
+        self.pending.is_open()
+    }
+
+    /// Fails the calls waiting on the connection and stops reading from it. The writer stops
+    /// once the slot lets go of the connection.
+    fn close(&self) {
+        self.pending.close();
+        self.reader.abort();
+    }
+
+    async fn request(
+        &self,
+        operation: &Operation,
+        trace_id: Option<&str>,
+    ) -> Result<Response, Error> {
+        let mut call = self.pending.register()?;
+        trace!(
+            "Sending request #{} [{:?}]: {:?}",
+            call.id(),
+            trace_id,
+            operation
+        );
+        let encoded = bincode::encode_to_vec(
+            RequestEnvelope {
+                id: call.id(),
+                trace_id: trace_id.map(str::to_string),
+                operation: operation.clone(),
+            },
+            bincode::config::standard(),
+        )?;
+
+        // Frames are written by a separate task, so a cancelled call never leaves half a frame
+        // on the connection.
+        self.requests
+            .send(self.codec.encode(encoded))
+            .await
+            .map_err(|_| "Connection is closed")?;
+
+        let mut abandoned = CloseIfAbandoned {
+            connection: self,
+            waiting: true,
+        };
+        let response = call.response().await;
+        abandoned.waiting = false;
+
+        response
+    }
+
+    async fn write_requests(
+        mut writer: WriteHalf<Box<dyn ServerStream>>,
+        mut requests: mpsc::Receiver<Vec<u8>>,
+        pending: Arc<PendingResponses>,
+    ) {
+        while let Some(frame) = requests.recv().await {
+            if let Err(err) = write_frame(&mut writer, &frame).await {
+                error!("Failed writing request: {:?}", err);
+                pending.close();
+                return;
+            }
+        }
+
+        if let Err(err) = writer.shutdown().await {
+            error!("Failed to shut down writer: {:?}", err);
+        }
+    }
+
+    async fn read_responses(
+        mut reader: ReadHalf<Box<dyn ServerStream>>,
+        pending: Arc<PendingResponses>,
+        codec: FrameCodec,
+    ) {
+        loop {
+            let frame = match read_frame(&mut reader, MAX_RESPONSE_SIZE).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    trace!("Connection closed");
+                    break;
+                }
+                Err(err) => {
+                    error!("Error while reading: {:?}", err);
+                    break;
+                }
+            };
+
+            let frame = match codec.decode(frame, MAX_RESPONSE_SIZE) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    error!("Failed decompressing response: {:?}", err);
+                    break;
+                }
+            };
+
+            match decode_untrusted(&frame[..]) {
+                Ok((envelope, ..)) => pending.resolve(envelope),
+                Err(err) => {
+                    error!("Failed decoding response: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        pending.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, DuplexStream, duplex},
+        time::timeout,
+    };
+
+    use super::*;
+
+    /// Answers the handshake, then never responds.
+    async fn silent_server(mut stream: DuplexStream) {
+        let mut preamble = [0; FRAMED_PREAMBLE.len()];
+        stream.read_exact(&mut preamble).await.unwrap();
+        read_frame(&mut stream, MAX_RESPONSE_SIZE).await.unwrap();
+        let response = bincode::encode_to_vec(
+            HelloResponse::Accepted(Hello::new(vec![])),
+            bincode::config::standard(),
+        )
+        .unwrap();
+        write_frame(&mut stream, &response).await.unwrap();
+
+        while read_frame(&mut stream, MAX_RESPONSE_SIZE)
+            .await
+            .is_ok_and(|frame| frame.is_some())
+        {}
+    }
+
+    #[tokio::test]
+    async fn timed_out_call_makes_the_slot_reconnect() {
+        let pool = ConnectionPool::new(1, 0);
+        let operation = Operation::IsGameOn("game".to_string());
+
+        let (client, server) = duplex(1024);
+        tokio::spawn(silent_server(server));
+        let call = pool.request(&operation, None, || async {
+            Ok(Box::new(client) as Box<dyn ServerStream>)
+        });
+        assert!(timeout(Duration::from_millis(50), call).await.is_err());
+
+        let call = pool.request(&operation, None, || async { Err("Reconnecting".into()) });
+        let err = timeout(Duration::from_millis(50), call)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Reconnecting");
+    }
+}
@@ -0,0 +1,109 @@
+use log::warn;
+use minignetcommon::{Error, Operation, Response};
+
+use crate::{
+    ClientConfig,
+    time::{sleep, timeout},
+};
+
+/// Runs `attempt` for the operation, retrying idempotent operations with exponential backoff
+/// when an attempt fails or times out.
+pub(crate) async fn with_retries<F, Fut>(
+    config: &ClientConfig,
+    op: &Operation,
+    mut attempt: F,
+) -> Result<Response, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, Error>>,
+{
+    let max_attempts = if op.is_idempotent() {
+        config.max_retries + 1
+    } else {
+        1
+    };
+    let mut backoff = config.initial_backoff;
+    let mut attempt_number = 1;
+
+    loop {
+        let result = match timeout(config.timeout, attempt()).await {
+            Ok(result) => result,
+            Err(err) => Err(err.into()),
+        };
+
+        match result {
+            Err(err) if attempt_number < max_attempts => {
+                warn!(
+                    "Attempt {}/{} failed: {}, retrying in {:?}",
+                    attempt_number, max_attempts, err, backoff
+                );
+                sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(config.max_backoff);
+                attempt_number += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use super::*;
+
+    fn config(max_retries: u32) -> ClientConfig {
+        ClientConfig {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            ..ClientConfig::default()
+        }
+    }
+
+    /// Runs the operation with an attempt that fails `failures` times before succeeding.
+    /// Returns the result and the number of attempts made.
+    async fn failing(
+        config: &ClientConfig,
+        op: Operation,
+        failures: u32,
+    ) -> (Result<Response, Error>, u32) {
+        let attempts = Cell::new(0);
+        let result = with_retries(config, &op, || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt <= failures {
+                    Err(format!("Failure #{}", attempt).into())
+                } else {
+                    Ok(Response::Ok)
+                }
+            }
+        })
+        .await;
+
+        (result, attempts.get())
+    }
+
+    #[tokio::test]
+    async fn idempotent_operation_is_retried_until_it_succeeds() {
+        let op = Operation::IsGameOn("game".to_string());
+
+        let (result, attempts) = failing(&config(3), op.clone(), 3).await;
+        assert!(matches!(result, Ok(Response::Ok)));
+        assert_eq!(attempts, 4);
+
+        let (result, attempts) = failing(&config(3), op, 4).await;
+        assert_eq!(result.unwrap_err().to_string(), "Failure #4");
+        assert_eq!(attempts, 4);
+    }
+
+    #[tokio::test]
+    async fn other_operations_are_attempted_once() {
+        let op = Operation::JoinSession("game".to_string(), "alice".to_string());
+
+        let (result, attempts) = failing(&config(3), op, 1).await;
+        assert_eq!(result.unwrap_err().to_string(), "Failure #1");
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use crate::ClientError;
+
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub(crate) use gloo_timers::future::sleep;
+#[cfg(feature = "native")]
+pub(crate) use tokio::time::sleep;
+
+/// Fails with `ClientError::Timeout` when `future` does not complete within `duration`.
+#[cfg(feature = "native")]
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, ClientError> {
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| ClientError::Timeout(duration))
+}
+
+/// Fails with `ClientError::Timeout` when `future` does not complete within `duration`.
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, ClientError> {
+    use futures_util::future::{Either, select};
+    use std::pin::pin;
+
+    match select(pin!(future), pin!(sleep(duration))).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(ClientError::Timeout(duration)),
+    }
+}
@@ -0,0 +1,139 @@
+use std::{path::Path, sync::Arc};
+
+use minignetcommon::Error;
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{
+        self, CertificateError, ClientConfig as RustlsConfig, DigitallySignedStruct, RootCertStore,
+        SignatureScheme,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{
+            CryptoProvider, ring::default_provider, verify_tls12_signature, verify_tls13_signature,
+        },
+        pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject},
+    },
+};
+
+/// How `MGNClient` encrypts its TCP connections and verifies the server, see
+/// `MGNClient::with_tls`. The server needs `--tls-cert` and `--tls-key`.
+#[derive(Clone)]
+pub struct TlsConfig {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TlsConfig {
+    /// Trusts servers whose certificate is signed by one of the certificate authorities in the
+    /// PEM file and issued for the server's host name.
+    pub fn with_ca_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(path)? {
+            roots.add(cert?)?;
+        }
+
+        let config = RustlsConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self::new(config))
+    }
+
+    /// Trusts only a server presenting the certificate in the PEM file, eg. a self-signed one.
+    /// Its issuer, host name and validity period are not checked.
+    pub fn with_pinned_certificate_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let pinned = CertificateDer::from_pem_file(path)?;
+        let verifier = PinnedCertificate {
+            pinned,
+            provider: Arc::new(default_provider()),
+        };
+
+        let config = RustlsConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        Ok(Self::new(config))
+    }
+
+    /// Name the certificate has to be issued for, and sent to the server. Defaults to the host
+    /// of the server address, or its IP address.
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Result<Self, Error> {
+        self.server_name = Some(ServerName::try_from(server_name.into())?);
+        Ok(self)
+    }
+
+    fn new(config: RustlsConfig) -> Self {
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name: None,
+        }
+    }
+
+    pub(crate) fn connector(&self) -> &TlsConnector {
+        &self.connector
+    }
+
+    pub(crate) fn server_name(&self) -> Option<&ServerName<'static>> {
+        self.server_name.as_ref()
+    }
+}
+
+#[derive(Debug)]
+struct PinnedCertificate {
+    pinned: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() != self.pinned.as_ref() {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    // The handshake signatures prove the server holds the pinned certificate's key.
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
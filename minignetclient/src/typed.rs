@@ -0,0 +1,158 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use bincode::{Decode, Encode};
+use minignetcommon::{Error, GamerIdType, Message, MessageAddress, decode_untrusted};
+
+use crate::{GameClient, MGNClient};
+
+/// Wraps a `GameClient` to exchange updates of type `U` and messages of type `M` instead of raw
+/// payload bytes.
+pub struct TypedClient<U, M, C = MGNClient> {
+    client: C,
+    serialization_config: bincode::config::Configuration,
+    _payloads: PhantomData<fn() -> (U, M)>,
+}
+
+impl<U, M, C: Clone> Clone for TypedClient<U, M, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            serialization_config: self.serialization_config,
+            _payloads: PhantomData,
+        }
+    }
+}
+
+impl<U, M, C> TypedClient<U, M, C>
+where
+    U: Encode + Decode<()>,
+    M: Encode + Decode<()>,
+    C: GameClient,
+{
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            serialization_config: bincode::config::standard(),
+            _payloads: PhantomData,
+        }
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    pub async fn send_update(&self, update: &U) -> Result<(), Error> {
+        let payload = bincode::encode_to_vec(update, self.serialization_config)?;
+        self.client.send_update(payload).await
+    }
+
+    /// Latest update of every gamer. Updates that fail to decode are reported as errors.
+    pub async fn get_previous_round_updates(
+        &self,
+    ) -> Result<HashMap<GamerIdType, Option<U>>, Error> {
+        let mut updates = HashMap::new();
+
+        for (gamer_id, update) in self.client.get_previous_round_updates().await? {
+            let update = match update {
+                Some(payload) => Some(self.decode(&payload)?),
+                None => None,
+            };
+            updates.insert(gamer_id, update);
+        }
+
+        Ok(updates)
+    }
+
+    pub async fn send_to(&self, to: MessageAddress, message: &M) -> Result<(), Error> {
+        let payload = bincode::encode_to_vec(message, self.serialization_config)?;
+        self.client
+            .send_message(Message::new(self.client.gamer_id().clone(), to, payload))
+            .await
+    }
+
+    /// Fetches and acknowledges all awaiting messages. A message whose payload fails to decode
+    /// is returned with the error, so a single malformed payload neither blocks the queue nor
+    /// gets lost unnoticed.
+    pub async fn fetch(&self) -> Result<Vec<(GamerIdType, Result<M, Error>)>, Error> {
+        let messages = self.client.fetch_all_messages().await?;
+        let Some(last_seq) = messages.last().map(|message| message.seq) else {
+            return Ok(vec![]);
+        };
+
+        let decoded = messages
+            .into_iter()
+            .map(|message| {
+                let payload = self.decode(&message.payload);
+                (message.from, payload)
+            })
+            .collect();
+
+        self.client.ack_messages(last_seq).await?;
+
+        Ok(decoded)
+    }
+
+    /// Decodes the payload of a message fetched through the untyped API, eg. in
+    /// `GameLoop::on_message`.
+    pub fn decode_message(&self, message: &Message) -> Result<M, Error> {
+        self.decode(&message.payload)
+    }
+
+    pub fn decode_update(&self, update: &[u8]) -> Result<U, Error> {
+        self.decode(update)
+    }
+
+    fn decode<T: Decode<()>>(&self, payload: &[u8]) -> Result<T, Error> {
+        let (value, _size) = decode_untrusted(payload)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MockClient, MockServer};
+
+    use super::*;
+
+    async fn typed_clients() -> (TypedClient<u32, String, MockClient>, MockClient) {
+        let server = MockServer::default();
+        let alice = server.client("game".to_string(), "alice".to_string());
+        let bob = server.client("game".to_string(), "bob".to_string());
+        alice.join_session().await.unwrap();
+        bob.join_session().await.unwrap();
+
+        (TypedClient::new(alice), bob)
+    }
+
+    #[tokio::test]
+    async fn fetch_returns_malformed_messages_with_the_error() {
+        let (alice, bob) = typed_clients().await;
+        let typed_bob: TypedClient<u32, String, MockClient> = TypedClient::new(bob.clone());
+
+        alice
+            .send_to(MessageAddress::All, &"hello".to_string())
+            .await
+            .unwrap();
+        let malformed = Message::new("alice".to_string(), MessageAddress::All, vec![0xff]);
+        alice.client().send_message(malformed).await.unwrap();
+
+        let messages = typed_bob.fetch().await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, "alice");
+        assert_eq!(messages[0].1.as_ref().unwrap(), "hello");
+        assert!(messages[1].1.is_err());
+
+        assert!(bob.fetch_all_messages().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_round_trip() {
+        let (alice, bob) = typed_clients().await;
+        let typed_bob: TypedClient<u32, String, MockClient> = TypedClient::new(bob);
+
+        alice.send_update(&42).await.unwrap();
+        let updates = typed_bob.get_previous_round_updates().await.unwrap();
+        assert_eq!(updates["alice"], Some(42));
+        assert_eq!(updates["bob"], None);
+    }
+}
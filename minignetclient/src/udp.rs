@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, trace, warn};
+use minignetcommon::{
+    Datagram, Error, GamerIdType, MAX_DATAGRAM_SIZE, SessionIdType, StateSnapshot,
+    decode_untrusted_within,
+};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket, lookup_host},
+    sync::Notify,
+    task::JoinHandle,
+    time::interval,
+};
+
+/// How often the channel subscribes again, well below the server's default
+/// `--keep-alive-timeout-ms`, so a gamer who only listens keeps receiving.
+const SUBSCRIBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Latest snapshot of every gamer the game has not taken yet.
+#[derive(Default)]
+struct PendingSnapshots {
+    snapshots: Mutex<HashMap<GamerIdType, StateSnapshot>>,
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+impl PendingSnapshots {
+    fn lock(&self) -> MutexGuard<'_, HashMap<GamerIdType, StateSnapshot>> {
+        self.snapshots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replaces the gamer's previous snapshot if the game did not take it yet.
+    fn put(&self, snapshot: StateSnapshot) {
+        self.lock().insert(snapshot.gamer_id.clone(), snapshot);
+        self.notify.notify_one();
+    }
+
+    /// The snapshot waiting the longest, judging by sequence numbers.
+    fn take(&self) -> Option<StateSnapshot> {
+        let mut snapshots = self.lock();
+        let gamer_id = snapshots
+            .values()
+            .min_by_key(|snapshot| snapshot.seq)?
+            .gamer_id
+            .clone();
+        snapshots.remove(&gamer_id)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+}
+
+/// Closes the pending snapshots when the reader task ends or is aborted.
+struct CloseOnDrop(Arc<PendingSnapshots>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Snapshots of the other gamers of the session received over the unreliable channel. Only the
+/// latest snapshot of every gamer is kept until the game takes it, a newer one replaces it and
+/// an older one is dropped.
+pub struct Snapshots {
+    pending: Arc<PendingSnapshots>,
+}
+
+impl Snapshots {
+    /// Waits for the next snapshot, `None` once the channel is closed.
+    pub async fn next(&mut self) -> Option<StateSnapshot> {
+        loop {
+            if let Some(snapshot) = self.pending.take() {
+                return Some(snapshot);
+            }
+            if self.pending.closed.load(Ordering::Acquire) {
+                return None;
+            }
+
+            self.pending.notify.notified().await;
+        }
+    }
+
+    /// The next snapshot if one already arrived, eg. to drain them once per frame.
+    pub fn try_next(&mut self) -> Option<StateSnapshot> {
+        self.pending.take()
+    }
+}
+
+/// UDP socket connected to the server's `--udp-addr`, for state updates where only the latest
+/// one counts and a lost one is not worth waiting for.
+pub(crate) struct UnreliableChannel {
+    socket: Arc<UdpSocket>,
+    session_id: SessionIdType,
+    gamer_id: GamerIdType,
+    /// From `Operation::GetChannelToken`, proves to the server the datagrams are the gamer's.
+    token: u64,
+    next_seq: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl UnreliableChannel {
+    pub(crate) async fn open<A: ToSocketAddrs>(
+        addr: A,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+        token: u64,
+    ) -> Result<(Self, Snapshots), Error> {
+        let server_addr = lookup_host(addr)
+            .await?
+            .next()
+            .ok_or("No socket addresses found")?;
+        let local_addr = if server_addr.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(server_addr).await?;
+        let socket = Arc::new(socket);
+
+        let subscribe = bincode::encode_to_vec(
+            Datagram::Subscribe(session_id.clone(), gamer_id.clone(), token),
+            bincode::config::standard(),
+        )?;
+        let pending = Arc::new(PendingSnapshots::default());
+        let reader = tokio::spawn(UnreliableChannel::read_snapshots(
+            socket.clone(),
+            subscribe,
+            pending.clone(),
+        ));
+
+        // Milliseconds since the epoch, so a restarted client does not start below the
+        // sequence numbers it used before and get its snapshots dropped as stale.
+        let first_seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(1);
+
+        let channel = Self {
+            socket,
+            session_id,
+            gamer_id,
+            token,
+            next_seq: AtomicU64::new(first_seq),
+            reader,
+        };
+
+        Ok((channel, Snapshots { pending }))
+    }
+
+    pub(crate) async fn send(&self, payload: Vec<u8>) -> Result<(), Error> {
+        let snapshot = StateSnapshot {
+            session_id: self.session_id.clone(),
+            gamer_id: self.gamer_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            payload,
+        };
+        let encoded = bincode::encode_to_vec(
+            Datagram::Update(self.token, snapshot),
+            bincode::config::standard(),
+        )?;
+        if encoded.len() > MAX_DATAGRAM_SIZE {
+            return Err(
+                format!("Update of {} bytes does not fit a datagram", encoded.len()).into(),
+            );
+        }
+
+        self.socket.send(&encoded).await?;
+
+        Ok(())
+    }
+
+    async fn read_snapshots(
+        socket: Arc<UdpSocket>,
+        subscribe: Vec<u8>,
+        pending: Arc<PendingSnapshots>,
+    ) {
+        let pending = CloseOnDrop(pending);
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let mut latest_seqs: HashMap<GamerIdType, u64> = HashMap::new();
+        let mut subscribe_interval = interval(SUBSCRIBE_INTERVAL);
+
+        loop {
+            let size = tokio::select! {
+                _ = subscribe_interval.tick() => {
+                    if let Err(err) = socket.send(&subscribe).await {
+                        error!("Failed subscribing to snapshots: {:?}", err);
+                    }
+                    continue;
+                }
+                received = socket.recv(&mut buf) => match received {
+                    Ok(size) => size,
+                    // Eg. the server is not listening yet, the next subscribe retries.
+                    Err(err) => {
+                        warn!("Error while receiving snapshot: {:?}", err);
+                        continue;
+                    }
+                },
+            };
+
+            let snapshot = match decode_untrusted_within(&buf[..size], MAX_DATAGRAM_SIZE) {
+                Ok((Datagram::Snapshot(snapshot), ..)) => snapshot,
+                Ok((datagram, ..)) => {
+                    warn!("Ignoring unexpected datagram: {:?}", datagram);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Failed decoding snapshot: {:?}", err);
+                    continue;
+                }
+            };
+
+            let latest_seq = latest_seqs.entry(snapshot.gamer_id.clone()).or_default();
+            if snapshot.seq <= *latest_seq {
+                trace!(
+                    "Dropping stale snapshot {} of {:?}",
+                    snapshot.seq, snapshot.gamer_id
+                );
+                continue;
+            }
+            *latest_seq = snapshot.seq;
+
+            pending.0.put(snapshot);
+        }
+    }
+}
+
+impl Drop for UnreliableChannel {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(gamer_id: &str, seq: u64) -> StateSnapshot {
+        StateSnapshot {
+            session_id: "game".to_string(),
+            gamer_id: gamer_id.to_string(),
+            seq,
+            payload: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_snapshot_of_every_gamer_is_kept() {
+        let pending = Arc::new(PendingSnapshots::default());
+        let mut snapshots = Snapshots {
+            pending: pending.clone(),
+        };
+
+        for seq in 1..=300 {
+            pending.put(snapshot("alice", seq));
+        }
+        pending.put(snapshot("bob", 7));
+
+        assert_eq!(snapshots.next().await.map(|snapshot| snapshot.seq), Some(7));
+        assert_eq!(snapshots.try_next().map(|snapshot| snapshot.seq), Some(300));
+        assert!(snapshots.try_next().is_none());
+
+        drop(CloseOnDrop(pending));
+        assert!(snapshots.next().await.is_none());
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    io::{Error as IoError, ErrorKind},
+    rc::Rc,
+};
+
+use futures_util::{
+    SinkExt, Stream, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use gloo_net::websocket::{Message, WebSocketError, futures::WebSocket};
+use log::{error, trace, warn};
+use minignetcommon::{
+    Compression, Error, FrameCodec, GamerIdType, Hello, HelloResponse, Operation, RequestEnvelope,
+    Response, SessionIdType, decode_untrusted,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    ClientConfig, GameClient, error::expect_hello, pending::PendingResponses, retry::with_retries,
+};
+
+/// Client for browser games, talking to the server's WebSocket listener (`--ws-addr`). Clones
+/// share one connection, opened on the first call and reopened after it breaks, and calls are
+/// multiplexed over it.
+#[derive(Clone)]
+pub struct WebSocketClient {
+    config: ClientConfig,
+    url: String,
+    connection: Rc<Mutex<Option<Rc<Connection>>>>,
+    trace_id: Option<String>,
+    pub session_id: SessionIdType,
+    pub gamer_id: GamerIdType,
+}
+
+impl WebSocketClient {
+    /// `url` is the address of the server's WebSocket listener, eg. `ws://localhost:8889`.
+    pub fn new<Url>(
+        url: Url,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+    ) -> Result<Self, IoError>
+    where
+        Url: Into<String>,
+    {
+        let url = url.into();
+        if !url.starts_with("ws://") && !url.starts_with("wss://") {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Not a WebSocket URL: {}", url),
+            ));
+        }
+
+        Ok(Self {
+            config: ClientConfig::default(),
+            url,
+            connection: Rc::new(Mutex::new(None)),
+            trace_id: None,
+            session_id,
+            gamer_id,
+        })
+    }
+
+    pub fn with_config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Tags every call with `trace_id`, which the server includes in its logs.
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    async fn connection(&self) -> Result<Rc<Connection>, Error> {
+        let mut connection = self.connection.lock().await;
+
+        if let Some(connection) = connection
+            .as_ref()
+            .filter(|connection| connection.pending.is_open())
+        {
+            return Ok(connection.clone());
+        }
+
+        trace!("Opening WebSocket connection to {}", self.url);
+        let opened = Rc::new(Connection::open(&self.url, self.config.compression_threshold).await?);
+        *connection = Some(opened.clone());
+
+        Ok(opened)
+    }
+
+    async fn exchange(&self, op: &Operation) -> Result<Response, Error> {
+        self.connection()
+            .await?
+            .request(op, self.trace_id.as_deref())
+            .await
+    }
+}
+
+impl GameClient for WebSocketClient {
+    fn session_id(&self) -> &SessionIdType {
+        &self.session_id
+    }
+
+    fn gamer_id(&self) -> &GamerIdType {
+        &self.gamer_id
+    }
+
+    async fn request(&self, op: Operation) -> Result<Response, Error> {
+        with_retries(&self.config, &op, || self.exchange(&op)).await
+    }
+}
+
+/// Largest response frame accepted from the server, once decompressed.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+struct Connection {
+    sink: Mutex<SplitSink<WebSocket, Message>>,
+    pending: Rc<PendingResponses>,
+    codec: FrameCodec,
+}
+
+impl Connection {
+    async fn open(url: &str, compression_threshold: usize) -> Result<Self, Error> {
+        let mut websocket = WebSocket::open(url)?;
+
+        let hello = bincode::encode_to_vec(
+            Hello::new(Compression::capabilities()),
+            bincode::config::standard(),
+        )?;
+        websocket.send(Message::Bytes(hello)).await?;
+
+        let frame = Connection::read_frame(&mut websocket)
+            .await?
+            .ok_or("Connection closed during handshake")?;
+        let (response, _size): (HelloResponse, usize) = decode_untrusted(&frame[..])?;
+        let hello = expect_hello(response)?;
+        let codec = FrameCodec::new(
+            Compression::negotiate(&hello.capabilities),
+            compression_threshold,
+        );
+
+        let (sink, stream) = websocket.split();
+        let pending = Rc::new(PendingResponses::new());
+        wasm_bindgen_futures::spawn_local(Connection::read_responses(
+            stream,
+            pending.clone(),
+            codec,
+        ));
+
+        Ok(Self {
+            sink: Mutex::new(sink),
+            pending,
+            codec,
+        })
+    }
+
+    async fn request(
+        &self,
+        operation: &Operation,
+        trace_id: Option<&str>,
+    ) -> Result<Response, Error> {
+        let mut call = self.pending.register()?;
+        trace!(
+            "Sending request #{} [{:?}]: {:?}",
+            call.id(),
+            trace_id,
+            operation
+        );
+        let encoded = bincode::encode_to_vec(
+            RequestEnvelope {
+                id: call.id(),
+                trace_id: trace_id.map(str::to_string),
+                operation: operation.clone(),
+            },
+            bincode::config::standard(),
+        )?;
+
+        if let Err(err) = self
+            .sink
+            .lock()
+            .await
+            .send(Message::Bytes(self.codec.encode(encoded)))
+            .await
+        {
+            error!("Failed writing request: {:?}", err);
+            self.pending.close();
+            return Err(err.into());
+        }
+
+        call.response().await
+    }
+
+    /// Every binary message carries one frame, see the server's WebSocket listener.
+    async fn read_frame<S>(stream: &mut S) -> Result<Option<Vec<u8>>, WebSocketError>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        while let Some(message) = stream.next().await {
+            match message? {
+                Message::Bytes(frame) => return Ok(Some(frame)),
+                Message::Text(_) => warn!("Ignoring text message"),
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn read_responses(
+        mut stream: SplitStream<WebSocket>,
+        pending: Rc<PendingResponses>,
+        codec: FrameCodec,
+    ) {
+        loop {
+            let frame = match Connection::read_frame(&mut stream).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    trace!("Connection closed");
+                    break;
+                }
+                Err(err) => {
+                    error!("Error while reading: {:?}", err);
+                    break;
+                }
+            };
+
+            let frame = match codec.decode(frame, MAX_RESPONSE_SIZE) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    error!("Failed decompressing response: {:?}", err);
+                    break;
+                }
+            };
+
+            match decode_untrusted(&frame[..]) {
+                Ok((envelope, ..)) => pending.resolve(envelope),
+                Err(err) => {
+                    error!("Failed decoding response: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        pending.close();
+    }
+}
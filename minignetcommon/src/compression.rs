@@ -0,0 +1,210 @@
+use std::io::{Error as IoError, ErrorKind};
+
+use log::error;
+
+/// Flag byte starting a frame sent as is on a connection using compression.
+const RAW_FRAME: u8 = 0;
+/// Flag byte starting a frame compressed with the connection's compression.
+const COMPRESSED_FRAME: u8 = 1;
+
+/// Frame compression, offered as a `Hello` capability named after the algorithm. Only the
+/// algorithms enabled by this crate's `zstd` and `lz4` features exist.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// The enabled algorithms, the preferred one first.
+    pub fn supported() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ]
+    }
+
+    /// Capabilities to offer in a `Hello`.
+    pub fn capabilities() -> Vec<String> {
+        Compression::supported()
+            .into_iter()
+            .map(|compression| compression.capability().to_string())
+            .collect()
+    }
+
+    pub const fn capability(self) -> &'static str {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => "lz4",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// The first compression among the capabilities in use on a connection, the server keeps
+    /// them in the order the client offered them.
+    pub fn negotiate(capabilities: &[String]) -> Option<Compression> {
+        capabilities.iter().find_map(|capability| {
+            Compression::supported()
+                .into_iter()
+                .find(|compression| compression.capability() == capability)
+        })
+    }
+
+    // Without any algorithm enabled the enum is empty and the arguments go unused.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, IoError> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::block::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    /// Fails with `ErrorKind::InvalidData` when the data is corrupt or decompresses to more
+    /// than `max_size` bytes.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn decompress(self, data: &[u8], max_size: usize) -> Result<Vec<u8>, IoError> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let (size, compressed) = lz4_flex::block::uncompressed_size(data)
+                    .map_err(|err| IoError::new(ErrorKind::InvalidData, err))?;
+                if size > max_size {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidData,
+                        format!("Frame of {} bytes exceeds the limit of {}", size, max_size),
+                    ));
+                }
+
+                lz4_flex::block::decompress(compressed, size)
+                    .map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(data, max_size)
+                .map_err(|err| IoError::new(ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+/// Applies the compression negotiated for a connection to the frames sent after the handshake.
+/// With a compression every frame starts with a flag byte telling whether the rest is
+/// compressed, frames below the threshold are not worth it. Without one frames are unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    compression: Option<Compression>,
+    threshold: usize,
+}
+
+impl FrameCodec {
+    /// Compresses frames of at least `threshold` bytes.
+    pub fn new(compression: Option<Compression>, threshold: usize) -> Self {
+        Self {
+            compression,
+            threshold,
+        }
+    }
+
+    pub fn encode(&self, frame: Vec<u8>) -> Vec<u8> {
+        let Some(compression) = self.compression else {
+            return frame;
+        };
+
+        if frame.len() >= self.threshold {
+            match compression.compress(&frame) {
+                Ok(compressed) if compressed.len() < frame.len() => {
+                    return [&[COMPRESSED_FRAME], &compressed[..]].concat();
+                }
+                Ok(_) => {}
+                Err(err) => error!("Failed compressing frame: {:?}", err),
+            }
+        }
+
+        [&[RAW_FRAME], &frame[..]].concat()
+    }
+
+    /// Fails with `ErrorKind::InvalidData` when the frame is malformed or decompresses to more
+    /// than `max_size` bytes.
+    pub fn decode(&self, frame: Vec<u8>, max_size: usize) -> Result<Vec<u8>, IoError> {
+        let Some(compression) = self.compression else {
+            return Ok(frame);
+        };
+
+        match frame.split_first() {
+            Some((&RAW_FRAME, data)) => Ok(data.to_vec()),
+            Some((&COMPRESSED_FRAME, data)) => compression.decompress(data, max_size),
+            _ => Err(IoError::new(
+                ErrorKind::InvalidData,
+                "Frame without a compression flag",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SIZE: usize = 1024 * 1024;
+
+    #[test]
+    fn frames_are_unchanged_without_compression() {
+        let codec = FrameCodec::new(None, 0);
+        let frame = vec![7; 64];
+        assert_eq!(codec.encode(frame.clone()), frame);
+        assert_eq!(codec.decode(frame.clone(), MAX_SIZE).unwrap(), frame);
+    }
+
+    #[test]
+    fn negotiate_picks_the_first_supported_capability() {
+        let capabilities = vec!["unknown".to_string()];
+        assert_eq!(Compression::negotiate(&capabilities), None);
+
+        for compression in Compression::supported() {
+            let capabilities = vec!["unknown".to_string(), compression.capability().to_string()];
+            assert_eq!(Compression::negotiate(&capabilities), Some(compression));
+        }
+    }
+
+    #[test]
+    fn frames_round_trip_with_every_compression() {
+        for compression in Compression::supported() {
+            let codec = FrameCodec::new(Some(compression), 16);
+
+            let small = vec![1, 2, 3];
+            let encoded = codec.encode(small.clone());
+            assert_eq!(encoded[0], RAW_FRAME);
+            assert_eq!(codec.decode(encoded, MAX_SIZE).unwrap(), small);
+
+            let large = vec![42; 4096];
+            let encoded = codec.encode(large.clone());
+            assert_eq!(encoded[0], COMPRESSED_FRAME);
+            assert!(encoded.len() < large.len());
+            assert_eq!(codec.decode(encoded, MAX_SIZE).unwrap(), large);
+        }
+    }
+
+    #[test]
+    fn oversized_and_malformed_frames_are_rejected() {
+        for compression in Compression::supported() {
+            let codec = FrameCodec::new(Some(compression), 16);
+            let encoded = codec.encode(vec![42; 4096]);
+
+            let err = codec.decode(encoded, 1024).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            let err = codec.decode(vec![], MAX_SIZE).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            let err = codec
+                .decode(vec![COMPRESSED_FRAME, 0xff, 0xff, 0xff], MAX_SIZE)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
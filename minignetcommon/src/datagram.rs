@@ -0,0 +1,42 @@
+use bincode::{Decode, Encode};
+
+use crate::{GamerIdType, SessionIdType};
+
+/// Largest payload a UDP datagram can carry over IPv4.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Latest state of one gamer, sent over the unreliable UDP channel. Snapshots can be lost,
+/// duplicated or reordered, receivers keep only the one with the highest `seq` per gamer.
+#[derive(Debug, Decode, Encode, Clone)]
+pub struct StateSnapshot {
+    pub session_id: SessionIdType,
+    pub gamer_id: GamerIdType,
+    /// Chosen by the sender, increasing with every snapshot it sends.
+    pub seq: u64,
+    pub payload: Vec<u8>,
+}
+
+/// One UDP datagram, there is no handshake and no framing. Client datagrams carry the gamer's
+/// token from `Operation::GetChannelToken`, which never leaves the reliable channel otherwise.
+#[derive(Debug, Decode, Encode, Clone)]
+pub enum Datagram {
+    /// Client to server: relay the snapshots of the session to the sending address. Repeated
+    /// by the client so the server does not forget it.
+    Subscribe(SessionIdType, GamerIdType, u64),
+    /// Server to client: the latest state of another gamer of the session.
+    Snapshot(StateSnapshot),
+    /// Client to server: the sender's latest state, which also subscribes it.
+    Update(u64, StateSnapshot),
+}
+
+impl Datagram {
+    /// The session and gamer the datagram is about.
+    pub fn gamer(&self) -> (&SessionIdType, &GamerIdType) {
+        match self {
+            Datagram::Subscribe(session_id, gamer_id, _) => (session_id, gamer_id),
+            Datagram::Snapshot(snapshot) | Datagram::Update(_, snapshot) => {
+                (&snapshot.session_id, &snapshot.gamer_id)
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{Error as IoError, ErrorKind};
+
+use bincode::{Decode, Encode};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{Operation, Response, decode_untrusted};
+
+/// First bytes a client sends to open a framed connection. A one-shot request starts with the
+/// bincode variant index of an `Operation`, which is always below 251, so it never matches.
+pub const FRAMED_PREAMBLE: [u8; 4] = [0xFF, b'M', b'G', b'N'];
+
+/// Version of the framed protocol. Bump it whenever an existing type changes its encoding;
+/// appending enum variants keeps older peers working and needs no bump.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest peer version this build still decodes correctly.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// First frame sent by both sides of a framed connection, the client's right after the preamble.
+/// A JSON lines connection may start with one as well.
+#[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hello {
+    pub protocol_version: u32,
+    /// Optional features the sender supports. The server answers with the ones both sides
+    /// support, which are then in use on the connection.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    pub fn new(capabilities: Vec<String>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version >= MIN_PROTOCOL_VERSION
+    }
+}
+
+#[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum HelloResponse {
+    Accepted(Hello),
+    /// The client version is not supported, the connection is closed after this frame.
+    Rejected {
+        protocol_version: u32,
+        min_protocol_version: u32,
+    },
+}
+
+/// A request on a framed connection. The id is chosen by the client and echoed in the response,
+/// so several requests can be in flight on one connection and answered in any order.
+#[derive(Debug, Decode, Encode, Clone)]
+pub struct RequestEnvelope {
+    pub id: u64,
+    /// Client supplied identifier included in the server logs, eg. to follow one game or player.
+    pub trace_id: Option<String>,
+    pub operation: Operation,
+}
+
+#[derive(Debug, Decode, Encode, Clone)]
+pub struct ResponseEnvelope {
+    pub id: u64,
+    pub trace_id: Option<String>,
+    pub response: Response,
+}
+
+/// Id of a request whose envelope fails to decode, eg. because it carries an operation this
+/// build does not know. The id is encoded first, so it is still readable.
+pub fn request_id(frame: &[u8]) -> Option<u64> {
+    decode_untrusted(frame).ok().map(|(id, _size)| id)
+}
+
+/// Appends `payload` to `buf` prefixed with its length as a big endian u32.
+pub fn push_frame(buf: &mut Vec<u8>, payload: &[u8]) -> Result<(), IoError> {
+    let size = u32::try_from(payload.len())
+        .map_err(|_| IoError::new(ErrorKind::InvalidInput, "Frame is too large"))?;
+
+    buf.extend_from_slice(&size.to_be_bytes());
+    buf.extend_from_slice(payload);
+
+    Ok(())
+}
+
+/// Splits the first frame pushed by `push_frame` off `bytes`, returning its payload and the
+/// bytes after it. Fails with `ErrorKind::InvalidData` when the frame is incomplete.
+pub fn split_frame(bytes: &[u8]) -> Result<(&[u8], &[u8]), IoError> {
+    let incomplete = || IoError::new(ErrorKind::InvalidData, "Incomplete frame");
+    let (size, rest) = bytes.split_first_chunk::<4>().ok_or_else(incomplete)?;
+    let size = u32::from_be_bytes(*size) as usize;
+    if rest.len() < size {
+        return Err(incomplete());
+    }
+
+    Ok(rest.split_at(size))
+}
+
+/// Writes `payload` prefixed with its length as a big endian u32.
+#[cfg(feature = "tokio")]
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    payload: &[u8],
+) -> Result<(), IoError> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    push_frame(&mut frame, payload)?;
+
+    writer.write_all(&frame).await
+}
+
+/// Reads one frame written by `write_frame`. Returns `None` when the connection was closed
+/// between frames, and fails with `ErrorKind::InvalidData` when the frame exceeds `max_size`.
+#[cfg(feature = "tokio")]
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<Vec<u8>>, IoError> {
+    let mut size = [0; 4];
+    match reader.read_exact(&mut size).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let size = u32::from_be_bytes(size) as usize;
+    if size > max_size {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the limit of {}", size, max_size),
+        ));
+    }
+
+    let mut payload = vec![0; size];
+    reader.read_exact(&mut payload).await?;
+
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_split_back_into_their_payloads() {
+        let mut bytes = vec![];
+        push_frame(&mut bytes, b"hello").unwrap();
+        push_frame(&mut bytes, b"").unwrap();
+
+        let (first, rest) = split_frame(&bytes).unwrap();
+        assert_eq!(first, b"hello");
+        let (second, rest) = split_frame(rest).unwrap();
+        assert!(second.is_empty() && rest.is_empty());
+
+        let err = split_frame(&bytes[..6]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn request_id_survives_an_unknown_operation() {
+        let mut frame = bincode::encode_to_vec(
+            RequestEnvelope {
+                id: 300,
+                trace_id: None,
+                operation: Operation::IsGameOn("s".to_string()),
+            },
+            bincode::config::standard(),
+        )
+        .unwrap();
+        // Replaces the operation's variant index with one this build does not know.
+        let variant = frame.len() - 3;
+        frame[variant] = 99;
+
+        assert!(decode_untrusted::<RequestEnvelope>(&frame).is_err());
+        assert_eq!(request_id(&frame), Some(300));
+    }
+}
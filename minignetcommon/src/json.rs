@@ -0,0 +1,17 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+/// First byte of a JSON lines connection, where every line holds one JSON `Operation` and is
+/// answered by one line holding the JSON `Response`. A bincode request starts with the variant
+/// index of its `Operation`, far below it.
+pub const JSON_START: u8 = b'{';
+
+/// Encodes the value as one line of JSON, including the line break.
+pub fn to_json_line<T: Serialize>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+pub fn from_json_line<T: DeserializeOwned>(line: &[u8]) -> Result<T, serde_json::Error> {
+    serde_json::from_slice(line)
+}
@@ -1,16 +1,77 @@
+mod compression;
+mod datagram;
+mod framing;
+#[cfg(feature = "json")]
+mod json;
+mod session;
+mod world;
+
 use std::collections::HashMap;
 
-use bincode::{Decode, Encode};
+use bincode::{Decode, Encode, error::DecodeError};
 
+#[cfg(feature = "tokio")]
 use log::{error, trace};
-use tokio::{io::AsyncReadExt, net::tcp::ReadHalf};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub use compression::{Compression, FrameCodec};
+pub use datagram::{Datagram, MAX_DATAGRAM_SIZE, StateSnapshot};
+pub use framing::{
+    FRAMED_PREAMBLE, Hello, HelloResponse, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, RequestEnvelope,
+    ResponseEnvelope, push_frame, request_id, split_frame,
+};
+#[cfg(feature = "tokio")]
+pub use framing::{read_frame, write_frame};
+#[cfg(feature = "json")]
+pub use json::{JSON_START, from_json_line, to_json_line};
+pub use session::{GameSession, GameState, MessageLimits, QueueOverflowPolicy};
+pub use world::WorldState;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
+/// Most memory decoding one message received from a peer may claim.
+pub const DECODE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Decodes a message received from a peer like `bincode::decode_from_slice` with the standard
+/// configuration. Without a limit a forged length prefix makes bincode allocate whatever it
+/// claims before reading a single element.
+pub fn decode_untrusted<T: Decode<()>>(bytes: &[u8]) -> Result<(T, usize), DecodeError> {
+    decode_with_limit::<T, DECODE_LIMIT>(bytes)
+}
+
+/// Like `decode_untrusted`, for messages of at most `max_size` bytes. The claimed memory is
+/// bounded by the smallest of 1, 4, 16 and 64 MiB holding `max_size`, bincode only takes the
+/// limit as a constant.
+pub fn decode_untrusted_within<T: Decode<()>>(
+    bytes: &[u8],
+    max_size: usize,
+) -> Result<(T, usize), DecodeError> {
+    const MIB: usize = 1024 * 1024;
+
+    if max_size <= MIB {
+        decode_with_limit::<T, MIB>(bytes)
+    } else if max_size <= 4 * MIB {
+        decode_with_limit::<T, { 4 * MIB }>(bytes)
+    } else if max_size <= 16 * MIB {
+        decode_with_limit::<T, { 16 * MIB }>(bytes)
+    } else {
+        decode_untrusted(bytes)
+    }
+}
+
+fn decode_with_limit<T: Decode<()>, const LIMIT: usize>(
+    bytes: &[u8],
+) -> Result<(T, usize), DecodeError> {
+    bincode::decode_from_slice(bytes, bincode::config::standard().with_limit::<LIMIT>())
+}
+
 pub type GamerIdType = String;
 pub type SessionIdType = String;
+pub type TeamIdType = String;
 
-pub async fn read_socket_till_end(reader: &mut ReadHalf<'_>) -> Result<Vec<u8>, Error> {
+#[cfg(feature = "tokio")]
+pub async fn read_socket_till_end<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, Error> {
     let mut buf: [u8; 1024] = [0; 1024];
     let mut bytes = vec![];
 
@@ -34,19 +95,62 @@ pub async fn read_socket_till_end(reader: &mut ReadHalf<'_>) -> Result<Vec<u8>,
 }
 
 #[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageAddress {
+    /// Every gamer in the session except the sender.
     All,
     One(GamerIdType),
+    /// Every member of the team except the sender.
+    Team(TeamIdType),
+    Many(Vec<GamerIdType>),
+    /// Every gamer in the session except the sender and the listed gamers.
+    AllExcept(Vec<GamerIdType>),
+    /// The gamer who joined the session first.
+    Host,
 }
 
 #[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
     pub from: GamerIdType,
     pub to: MessageAddress,
     pub payload: Vec<u8>,
+    /// Optional application defined tag, so clients can dispatch without decoding the payload.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub kind: Option<String>,
+    /// Per-recipient sequence number, assigned by the server when the message is queued.
+    /// Clients acknowledge delivery up to a sequence number with `AckMessages`.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub seq: u64,
+    /// Session-wide message id, assigned by the server in the order messages are received.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub id: u64,
+    /// Milliseconds since the UNIX epoch when the server received the message.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub received_at: u64,
 }
 
-#[derive(Debug, Decode, Encode)]
+impl Message {
+    pub fn new(from: GamerIdType, to: MessageAddress, payload: Vec<u8>) -> Self {
+        Self {
+            from,
+            to,
+            payload,
+            kind: None,
+            seq: 0,
+            id: 0,
+            received_at: 0,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: impl Into<String>) -> Self {
+        self.kind = Some(kind.into());
+        self
+    }
+}
+
+#[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum Operation {
     JoinSession(SessionIdType, GamerIdType),
     ResetSession(SessionIdType),
@@ -59,13 +163,116 @@ pub enum Operation {
     GetPreviousRoundUpdates(SessionIdType),
     SendMessage(SessionIdType, Message),
     FetchAllMessages(SessionIdType, GamerIdType),
+    AckMessages(SessionIdType, GamerIdType, u64),
+    AssignTeam(SessionIdType, GamerIdType, TeamIdType),
+    LeaveTeam(SessionIdType, GamerIdType),
+    GetTeams(SessionIdType),
+    /// Token the gamer's datagrams carry on the UDP channel, so the server only relays to and
+    /// from addresses of clients that asked for it over the reliable channel.
+    GetChannelToken(SessionIdType, GamerIdType),
+}
+
+impl Operation {
+    pub fn session_id(&self) -> &SessionIdType {
+        match self {
+            Operation::JoinSession(session_id, _)
+            | Operation::ResetSession(session_id)
+            | Operation::StartSession(session_id)
+            | Operation::EndSession(session_id)
+            | Operation::IsGamerTurn(session_id, _)
+            | Operation::NextGamer(session_id)
+            | Operation::IsGameOn(session_id)
+            | Operation::SendUpdate(session_id, _, _)
+            | Operation::GetPreviousRoundUpdates(session_id)
+            | Operation::SendMessage(session_id, _)
+            | Operation::FetchAllMessages(session_id, _)
+            | Operation::AckMessages(session_id, _, _)
+            | Operation::AssignTeam(session_id, _, _)
+            | Operation::LeaveTeam(session_id, _)
+            | Operation::GetTeams(session_id)
+            | Operation::GetChannelToken(session_id, _) => session_id,
+        }
+    }
+
+    /// Operations that only read state and are safe to repeat when an attempt failed.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Operation::IsGamerTurn(..)
+                | Operation::IsGameOn(..)
+                | Operation::GetPreviousRoundUpdates(..)
+                | Operation::FetchAllMessages(..)
+                | Operation::GetTeams(..)
+                | Operation::GetChannelToken(..)
+        )
+    }
+
+    /// The gamer issuing the operation, when the operation identifies one.
+    pub fn gamer_id(&self) -> Option<&GamerIdType> {
+        match self {
+            Operation::JoinSession(_, gamer_id)
+            | Operation::IsGamerTurn(_, gamer_id)
+            | Operation::SendUpdate(_, gamer_id, _)
+            | Operation::FetchAllMessages(_, gamer_id)
+            | Operation::AckMessages(_, gamer_id, _)
+            | Operation::AssignTeam(_, gamer_id, _)
+            | Operation::LeaveTeam(_, gamer_id)
+            | Operation::GetChannelToken(_, gamer_id) => Some(gamer_id),
+            Operation::SendMessage(_, message) => Some(&message.from),
+            Operation::ResetSession(_)
+            | Operation::StartSession(_)
+            | Operation::EndSession(_)
+            | Operation::NextGamer(_)
+            | Operation::IsGameOn(_)
+            | Operation::GetPreviousRoundUpdates(_)
+            | Operation::GetTeams(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Decode, Encode, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorReason {
+    UnknownRecipient,
+    QueueFull,
+    PayloadTooLarge,
+    RateLimited,
+    RequestTooLarge,
+    Timeout,
+    /// Client and server protocol versions cannot talk to each other.
+    IncompatibleVersion,
+    /// The request could not be decoded, eg. an operation added in a newer version.
+    UnsupportedRequest,
 }
 
 #[derive(Debug, Decode, Encode, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum Response {
     Ok,
     Error,
     OkWithBool(bool),
     OkWithPreviousRoundUpdates(HashMap<GamerIdType, Option<Vec<u8>>>),
     OkWithMessages(Vec<Message>),
+    OkWithTeams(HashMap<TeamIdType, Vec<GamerIdType>>),
+    ErrorWithReason(ErrorReason),
+    OkWithToken(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::error::DecodeError;
+
+    use super::*;
+
+    #[test]
+    fn decode_untrusted_within_bounds_claimed_memory() {
+        let payload = vec![7u8; 2 * 1024 * 1024];
+        let bytes = bincode::encode_to_vec(&payload, bincode::config::standard()).unwrap();
+
+        let err = decode_untrusted_within::<Vec<u8>>(&bytes, 1024 * 1024).unwrap_err();
+        assert!(matches!(err, DecodeError::LimitExceeded));
+
+        let (decoded, size) = decode_untrusted_within::<Vec<u8>>(&bytes, bytes.len()).unwrap();
+        assert_eq!((decoded, size), (payload, bytes.len()));
+    }
 }
@@ -0,0 +1,715 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::{BuildHasher, RandomState},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info, warn};
+
+use crate::{ErrorReason, GamerIdType, Message, MessageAddress, TeamIdType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueOverflowPolicy {
+    /// Refuse the new message and tell the sender the queue is full.
+    RejectSender,
+    /// Accept the new message and discard the oldest unacknowledged one.
+    DropOldest,
+}
+
+impl FromStr for QueueOverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject-sender" => Ok(QueueOverflowPolicy::RejectSender),
+            "drop-oldest" => Ok(QueueOverflowPolicy::DropOldest),
+            _ => Err(format!(
+                "Unknown queue overflow policy {:?}, expected reject-sender or drop-oldest",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for QueueOverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueOverflowPolicy::RejectSender => write!(f, "reject-sender"),
+            QueueOverflowPolicy::DropOldest => write!(f, "drop-oldest"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MessageLimits {
+    pub max_queued_messages: usize,
+    pub max_payload_size: usize,
+    pub overflow_policy: QueueOverflowPolicy,
+    /// Payload bytes one fetch returns at most, the oldest message is returned even when it is
+    /// larger.
+    pub max_fetch_size: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_queued_messages: 1024,
+            max_payload_size: 64 * 1024,
+            overflow_policy: QueueOverflowPolicy::RejectSender,
+            max_fetch_size: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct UserState {
+    /// Only the latest update is kept, it replaces the previous one.
+    update: Option<Vec<u8>>,
+    awaiting_messages: Vec<Message>,
+    last_message_seq: u64,
+    team: Option<TeamIdType>,
+    /// Proves a datagram comes from a client that asked for it over the reliable channel.
+    channel_token: u64,
+}
+
+impl UserState {
+    fn new() -> Self {
+        Self {
+            channel_token: new_channel_token(),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn add_update(&mut self, update: Vec<u8>) {
+        self.update = Some(update);
+    }
+
+    pub(crate) fn is_queue_full(&self, limits: &MessageLimits) -> bool {
+        self.awaiting_messages.len() >= limits.max_queued_messages
+    }
+
+    pub(crate) fn enqueue_message(&mut self, mut message: Message, limits: &MessageLimits) {
+        while limits.overflow_policy == QueueOverflowPolicy::DropOldest
+            && self.is_queue_full(limits)
+            && !self.awaiting_messages.is_empty()
+        {
+            let dropped = self.awaiting_messages.remove(0);
+            warn!(
+                "Message queue is full, dropping message #{} (seq {})",
+                dropped.id, dropped.seq
+            );
+        }
+
+        self.last_message_seq += 1;
+        message.seq = self.last_message_seq;
+        self.awaiting_messages.push(message);
+    }
+
+    /// Drops every awaiting message up to and including `seq`.
+    pub(crate) fn ack_messages(&mut self, seq: u64) {
+        self.awaiting_messages.retain(|message| message.seq > seq);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.update = None;
+    }
+}
+
+/// Hashed with the process' random SipHash keys, so it cannot be guessed from the outside.
+fn new_channel_token() -> u64 {
+    static TOKENS: AtomicU64 = AtomicU64::new(0);
+
+    RandomState::new().hash_one((SystemTime::now(), TOKENS.fetch_add(1, Ordering::Relaxed)))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GameState {
+    Join,
+    Game,
+    Over,
+}
+
+#[derive(Debug)]
+pub struct GameSession {
+    user_states: HashMap<GamerIdType, UserState>,
+    sequence: Vec<GamerIdType>,
+    current_gamer_index: usize,
+    state: GameState,
+    last_message_id: u64,
+    limits: MessageLimits,
+}
+
+impl GameSession {
+    pub fn new(limits: MessageLimits) -> Self {
+        Self {
+            user_states: HashMap::new(),
+            current_gamer_index: 0,
+            state: GameState::Join,
+            sequence: vec![],
+            last_message_id: 0,
+            limits,
+        }
+    }
+
+    pub fn join(&mut self, gamer_id: GamerIdType) {
+        if self.user_states.contains_key(&gamer_id) {
+            // When it already exists - consider signalling so the client can fetch the
+            // previous state (aka re-join).
+            return;
+        }
+
+        self.user_states.insert(gamer_id.clone(), UserState::new());
+
+        self.sequence.push(gamer_id);
+    }
+
+    pub fn has_gamer(&self, gamer_id: &GamerIdType) -> bool {
+        self.user_states.contains_key(gamer_id)
+    }
+
+    /// Token of the gamer's datagrams on the UDP channel, `None` for unknown gamers.
+    pub fn channel_token(&self, gamer_id: &GamerIdType) -> Option<u64> {
+        self.user_states
+            .get(gamer_id)
+            .map(|user_state| user_state.channel_token)
+    }
+
+    pub fn is_gamer_turn(&self, gamer_id: GamerIdType) -> bool {
+        if self.state != GameState::Game {
+            return false;
+        }
+
+        self.sequence
+            .iter()
+            .position(|id| id == &gamer_id)
+            .map(|pos| pos == self.current_gamer_index)
+            .unwrap_or(false)
+    }
+
+    pub fn is_game_on(&self) -> bool {
+        self.state == GameState::Game
+    }
+
+    pub fn reset(&mut self) {
+        self.state = GameState::Join;
+        self.current_gamer_index = 0;
+
+        for (_, user_state) in self.user_states.iter_mut() {
+            user_state.reset();
+        }
+    }
+
+    pub fn start(&mut self) {
+        if self.state == GameState::Join {
+            self.state = GameState::Game;
+            info!("Session has started");
+        } else {
+            error!("Starting a session that is not in JOIN state");
+        }
+    }
+
+    pub fn end(&mut self) {
+        if self.state == GameState::Game {
+            self.state = GameState::Over;
+        } else {
+            error!("Ending a session that is not in GAME state");
+        }
+    }
+
+    pub fn check_payload_size(&self, payload: &[u8]) -> Result<(), ErrorReason> {
+        if payload.len() > self.limits.max_payload_size {
+            error!("Payload of {} bytes exceeds the limit", payload.len());
+            return Err(ErrorReason::PayloadTooLarge);
+        }
+
+        Ok(())
+    }
+
+    pub fn add_update(&mut self, gamer_id: GamerIdType, update: Vec<u8>) -> bool {
+        match self.user_states.get_mut(&gamer_id) {
+            Some(user_state) => {
+                user_state.add_update(update);
+                true
+            }
+            None => {
+                error!("Gamer is missing missing");
+                false
+            }
+        }
+    }
+
+    pub fn save_message(&mut self, mut message: Message) -> Result<(), ErrorReason> {
+        self.check_payload_size(&message.payload)?;
+
+        self.last_message_id += 1;
+        message.id = self.last_message_id;
+        message.received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        info!(
+            "Saving message #{} from {:?} to {:?} (kind: {:?})",
+            message.id, message.from, message.to, message.kind
+        );
+
+        let recipients = match self.message_recipients(&message) {
+            Some(recipients) => recipients,
+            None => {
+                error!("Message #{} has unknown recipients", message.id);
+                return Err(ErrorReason::UnknownRecipient);
+            }
+        };
+
+        if self.limits.overflow_policy == QueueOverflowPolicy::RejectSender
+            && recipients
+                .iter()
+                .filter_map(|gamer_id| self.user_states.get(gamer_id))
+                .any(|user_state| user_state.is_queue_full(&self.limits))
+        {
+            error!(
+                "Message #{} rejected, a recipient queue is full",
+                message.id
+            );
+            return Err(ErrorReason::QueueFull);
+        }
+
+        for gamer_id in recipients {
+            if let Some(user_state) = self.user_states.get_mut(&gamer_id) {
+                user_state.enqueue_message(message.clone(), &self.limits);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the address of the message into gamer ids, each listed once. Returns `None`
+    /// when an explicitly addressed gamer is not part of the session.
+    fn message_recipients(&self, message: &Message) -> Option<Vec<GamerIdType>> {
+        let others = || {
+            self.sequence
+                .iter()
+                .filter(|gamer_id| *gamer_id != &message.from)
+        };
+
+        let recipients: Vec<GamerIdType> = match &message.to {
+            MessageAddress::All => others().cloned().collect(),
+            MessageAddress::One(gamer_id) => vec![gamer_id.clone()],
+            MessageAddress::Team(team) => others()
+                .filter(|gamer_id| {
+                    self.user_states
+                        .get(*gamer_id)
+                        .is_some_and(|user_state| user_state.team.as_ref() == Some(team))
+                })
+                .cloned()
+                .collect(),
+            MessageAddress::Many(gamer_ids) => {
+                let mut listed = HashSet::new();
+                gamer_ids
+                    .iter()
+                    .filter(|gamer_id| listed.insert(*gamer_id))
+                    .cloned()
+                    .collect()
+            }
+            MessageAddress::AllExcept(excluded) => others()
+                .filter(|gamer_id| !excluded.contains(gamer_id))
+                .cloned()
+                .collect(),
+            MessageAddress::Host => vec![self.sequence.first()?.clone()],
+        };
+
+        if recipients
+            .iter()
+            .all(|gamer_id| self.user_states.contains_key(gamer_id))
+        {
+            Some(recipients)
+        } else {
+            None
+        }
+    }
+
+    pub fn assign_team(&mut self, gamer_id: GamerIdType, team: Option<TeamIdType>) -> bool {
+        match self.user_states.get_mut(&gamer_id) {
+            Some(user_state) => {
+                user_state.team = team;
+                true
+            }
+            None => {
+                error!("Gamer is missing");
+                false
+            }
+        }
+    }
+
+    pub fn teams(&self) -> HashMap<TeamIdType, Vec<GamerIdType>> {
+        let mut teams: HashMap<TeamIdType, Vec<GamerIdType>> = HashMap::new();
+
+        for gamer_id in &self.sequence {
+            if let Some(team) = self
+                .user_states
+                .get(gamer_id)
+                .and_then(|user_state| user_state.team.as_ref())
+            {
+                teams
+                    .entry(team.clone())
+                    .or_default()
+                    .push(gamer_id.clone());
+            }
+        }
+
+        teams
+    }
+
+    /// Messages stay queued until the gamer acknowledges them, so a failed reply does not
+    /// lose them. Returns the oldest ones whose payloads fit in `max_fetch_size`, the rest
+    /// follow once these are acknowledged.
+    pub fn gamer_messages(&self, gamer_id: GamerIdType) -> Vec<Message> {
+        let Some(user_state) = self.user_states.get(&gamer_id) else {
+            return vec![];
+        };
+
+        let mut fetch_size = 0;
+        let count = user_state
+            .awaiting_messages
+            .iter()
+            .take_while(|message| {
+                fetch_size += message.payload.len();
+                fetch_size <= self.limits.max_fetch_size
+            })
+            .count()
+            .max(1);
+
+        user_state
+            .awaiting_messages
+            .iter()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    pub fn ack_gamer_messages(&mut self, gamer_id: GamerIdType, seq: u64) -> bool {
+        match self.user_states.get_mut(&gamer_id) {
+            Some(user_state) => {
+                user_state.ack_messages(seq);
+                true
+            }
+            None => {
+                error!("Gamer is missing");
+                false
+            }
+        }
+    }
+
+    /// Latest update of every gamer, `None` for gamers who did not send one since the reset.
+    pub fn previous_round_updates(&self) -> HashMap<GamerIdType, Option<Vec<u8>>> {
+        self.user_states
+            .iter()
+            .map(|(gamer_id, user_state)| (gamer_id.clone(), user_state.update.clone()))
+            .collect()
+    }
+
+    /// Passes the turn to the next gamer, fails when nobody joined the session.
+    pub fn next_gamer(&mut self) -> bool {
+        if self.sequence.is_empty() {
+            error!("No gamer to pass the turn to");
+            return false;
+        }
+
+        self.current_gamer_index = (self.current_gamer_index + 1) % self.sequence.len();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(limits: MessageLimits) -> GameSession {
+        let mut session = GameSession::new(limits);
+        for gamer_id in ["alice", "bob", "carol", "dave"] {
+            session.join(gamer_id.to_string());
+        }
+        session.assign_team("alice".to_string(), Some("red".to_string()));
+        session.assign_team("carol".to_string(), Some("red".to_string()));
+        session
+    }
+
+    fn send(session: &mut GameSession, from: &str, to: MessageAddress) -> Result<(), ErrorReason> {
+        session.save_message(Message::new(from.to_string(), to, vec![1, 2, 3]))
+    }
+
+    /// Gamers who have a message queued, in join order.
+    fn recipients(session: &GameSession) -> Vec<&str> {
+        session
+            .sequence
+            .iter()
+            .filter(|gamer_id| !session.gamer_messages(gamer_id.to_string()).is_empty())
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn gamers(gamer_ids: &[&str]) -> Vec<GamerIdType> {
+        gamer_ids
+            .iter()
+            .map(|gamer_id| gamer_id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn all_reaches_everyone_but_the_sender() {
+        let mut session = session(MessageLimits::default());
+        send(&mut session, "alice", MessageAddress::All).unwrap();
+        assert_eq!(recipients(&session), ["bob", "carol", "dave"]);
+    }
+
+    #[test]
+    fn one_reaches_the_gamer() {
+        let mut session = session(MessageLimits::default());
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::One("dave".to_string()),
+        )
+        .unwrap();
+        assert_eq!(recipients(&session), ["dave"]);
+    }
+
+    #[test]
+    fn team_reaches_the_other_members() {
+        let mut session = session(MessageLimits::default());
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::Team("red".to_string()),
+        )
+        .unwrap();
+        assert_eq!(recipients(&session), ["carol"]);
+    }
+
+    #[test]
+    fn many_reaches_the_listed_gamers() {
+        let mut session = session(MessageLimits::default());
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::Many(gamers(&["bob", "dave"])),
+        )
+        .unwrap();
+        assert_eq!(recipients(&session), ["bob", "dave"]);
+    }
+
+    #[test]
+    fn many_reaches_a_gamer_listed_twice_once() {
+        let mut session = session(MessageLimits {
+            max_queued_messages: 1,
+            ..MessageLimits::default()
+        });
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::Many(gamers(&["bob", "dave", "bob"])),
+        )
+        .unwrap();
+        assert_eq!(session.gamer_messages("bob".to_string()).len(), 1);
+        assert_eq!(
+            send(
+                &mut session,
+                "alice",
+                MessageAddress::Many(gamers(&["bob", "bob"]))
+            ),
+            Err(ErrorReason::QueueFull)
+        );
+        assert_eq!(session.gamer_messages("bob".to_string())[0].seq, 1);
+    }
+
+    #[test]
+    fn all_except_skips_the_listed_gamers() {
+        let mut session = session(MessageLimits::default());
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::AllExcept(gamers(&["carol"])),
+        )
+        .unwrap();
+        assert_eq!(recipients(&session), ["bob", "dave"]);
+    }
+
+    #[test]
+    fn host_reaches_the_first_gamer() {
+        let mut session = session(MessageLimits::default());
+        send(&mut session, "dave", MessageAddress::Host).unwrap();
+        assert_eq!(recipients(&session), ["alice"]);
+    }
+
+    #[test]
+    fn unknown_recipient_is_rejected() {
+        let mut session = session(MessageLimits::default());
+        assert_eq!(
+            send(
+                &mut session,
+                "alice",
+                MessageAddress::One("eve".to_string())
+            ),
+            Err(ErrorReason::UnknownRecipient)
+        );
+        assert_eq!(
+            send(
+                &mut session,
+                "alice",
+                MessageAddress::Many(gamers(&["bob", "eve"]))
+            ),
+            Err(ErrorReason::UnknownRecipient)
+        );
+        assert!(recipients(&session).is_empty());
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let mut session = session(MessageLimits {
+            max_payload_size: 2,
+            ..MessageLimits::default()
+        });
+        assert_eq!(
+            send(&mut session, "alice", MessageAddress::All),
+            Err(ErrorReason::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn reject_sender_keeps_the_full_queue() {
+        let mut session = session(MessageLimits {
+            max_queued_messages: 1,
+            overflow_policy: QueueOverflowPolicy::RejectSender,
+            ..MessageLimits::default()
+        });
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::One("bob".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            send(
+                &mut session,
+                "carol",
+                MessageAddress::One("bob".to_string())
+            ),
+            Err(ErrorReason::QueueFull)
+        );
+
+        let messages = session.gamer_messages("bob".to_string());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].from, "alice");
+    }
+
+    #[test]
+    fn drop_oldest_makes_room_for_the_new_message() {
+        let mut session = session(MessageLimits {
+            max_queued_messages: 1,
+            overflow_policy: QueueOverflowPolicy::DropOldest,
+            ..MessageLimits::default()
+        });
+        send(
+            &mut session,
+            "alice",
+            MessageAddress::One("bob".to_string()),
+        )
+        .unwrap();
+        send(
+            &mut session,
+            "carol",
+            MessageAddress::One("bob".to_string()),
+        )
+        .unwrap();
+
+        let messages = session.gamer_messages("bob".to_string());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].from, "carol");
+        assert_eq!(messages[0].seq, 2);
+    }
+
+    #[test]
+    fn ack_drops_messages_up_to_the_sequence_number() {
+        let mut session = session(MessageLimits::default());
+        for _ in 0..3 {
+            send(
+                &mut session,
+                "alice",
+                MessageAddress::One("bob".to_string()),
+            )
+            .unwrap();
+        }
+
+        assert!(session.ack_gamer_messages("bob".to_string(), 2));
+        let seqs: Vec<u64> = session
+            .gamer_messages("bob".to_string())
+            .iter()
+            .map(|message| message.seq)
+            .collect();
+        assert_eq!(seqs, [3]);
+    }
+
+    #[test]
+    fn full_queue_is_fetched_in_bounded_batches() {
+        let limits = MessageLimits {
+            max_queued_messages: 10,
+            max_fetch_size: 7,
+            ..MessageLimits::default()
+        };
+        let mut session = session(limits);
+        while send(
+            &mut session,
+            "alice",
+            MessageAddress::One("bob".to_string()),
+        )
+        .is_ok()
+        {}
+
+        let mut fetched = vec![];
+        loop {
+            let messages = session.gamer_messages("bob".to_string());
+            let Some(last) = messages.last() else {
+                break;
+            };
+            assert_eq!(messages.len(), 2);
+            assert!(session.ack_gamer_messages("bob".to_string(), last.seq));
+            fetched.extend(messages.iter().map(|message| message.seq));
+        }
+        assert_eq!(fetched, (1..=10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn oversized_message_is_fetched_alone() {
+        let mut session = session(MessageLimits {
+            max_fetch_size: 2,
+            ..MessageLimits::default()
+        });
+        for _ in 0..2 {
+            send(
+                &mut session,
+                "alice",
+                MessageAddress::One("bob".to_string()),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(session.gamer_messages("bob".to_string()).len(), 1);
+    }
+
+    #[test]
+    fn only_the_latest_update_is_kept() {
+        let mut session = session(MessageLimits::default());
+        assert!(session.add_update("alice".to_string(), vec![1]));
+        assert!(session.add_update("alice".to_string(), vec![2]));
+
+        let updates = session.previous_round_updates();
+        assert_eq!(updates["alice"], Some(vec![2]));
+        assert_eq!(updates["bob"], None);
+
+        session.reset();
+        assert_eq!(session.previous_round_updates()["alice"], None);
+    }
+}
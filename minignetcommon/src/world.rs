@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use crate::{
+    GamerIdType, Message, Operation, Response, SessionIdType, TeamIdType,
+    session::{GameSession, MessageLimits},
+};
+
+/// All sessions known to a server. `handle` applies an `Operation` and produces the `Response`
+/// sent back to the client, independently of how the operation arrived.
+#[derive(Debug)]
+pub struct WorldState {
+    sessions: HashMap<SessionIdType, GameSession>,
+    message_limits: MessageLimits,
+}
+
+impl WorldState {
+    pub fn new(message_limits: MessageLimits) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            message_limits,
+        }
+    }
+
+    pub fn session(&self, session_id: &SessionIdType) -> Option<&GameSession> {
+        self.sessions.get(session_id)
+    }
+
+    pub fn handle(&mut self, operation: Operation) -> Response {
+        match operation {
+            Operation::JoinSession(session_id, gamer_id) => self.join_session(session_id, gamer_id),
+            Operation::ResetSession(session_id) => self.reset_session(session_id),
+            Operation::StartSession(session_id) => self.start_session(session_id),
+            Operation::EndSession(session_id) => self.end_session(session_id),
+            Operation::IsGamerTurn(session_id, gamer_id) => {
+                self.is_gamer_turn(session_id, gamer_id)
+            }
+            Operation::IsGameOn(session_id) => self.is_game_on(session_id),
+            Operation::SendUpdate(session_id, gamer_id, update) => {
+                self.send_update(session_id, gamer_id, update)
+            }
+            Operation::GetPreviousRoundUpdates(session_id) => {
+                self.get_previous_round_updates(session_id)
+            }
+            Operation::SendMessage(session_id, message) => self.send_message(session_id, message),
+            Operation::FetchAllMessages(session_id, gamer_id) => {
+                self.fetch_all_messages(session_id, gamer_id)
+            }
+            Operation::NextGamer(session_id) => self.next_gamer(session_id),
+            Operation::AckMessages(session_id, gamer_id, seq) => {
+                self.ack_messages(session_id, gamer_id, seq)
+            }
+            Operation::AssignTeam(session_id, gamer_id, team) => {
+                self.assign_team(session_id, gamer_id, Some(team))
+            }
+            Operation::LeaveTeam(session_id, gamer_id) => {
+                self.assign_team(session_id, gamer_id, None)
+            }
+            Operation::GetTeams(session_id) => self.get_teams(session_id),
+            Operation::GetChannelToken(session_id, gamer_id) => {
+                self.get_channel_token(session_id, gamer_id)
+            }
+        }
+    }
+
+    fn join_session(&mut self, session_id: SessionIdType, gamer_id: GamerIdType) -> Response {
+        let message_limits = self.message_limits;
+        let session = self
+            .sessions
+            .entry(session_id)
+            .or_insert_with(|| GameSession::new(message_limits));
+
+        session.join(gamer_id);
+
+        Response::Ok
+    }
+
+    fn reset_session(&mut self, session_id: SessionIdType) -> Response {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) => session.reset(),
+            None => {
+                error!("Session {:?}, it does not exist", session_id);
+                return Response::Error;
+            }
+        };
+
+        Response::Ok
+    }
+
+    fn start_session(&mut self, session_id: SessionIdType) -> Response {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) => session.start(),
+            None => {
+                error!("Cannot start session {:?}, it does not exist", session_id);
+                return Response::Error;
+            }
+        };
+
+        Response::Ok
+    }
+
+    fn end_session(&mut self, session_id: SessionIdType) -> Response {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) => session.end(),
+            None => {
+                error!("Cannot end session {:?}, it does not exist", session_id);
+                return Response::Error;
+            }
+        };
+
+        Response::Ok
+    }
+
+    fn is_game_on(&mut self, session_id: SessionIdType) -> Response {
+        match self.sessions.get(&session_id) {
+            Some(session) => Response::OkWithBool(session.is_game_on()),
+            None => {
+                error!("Missing session");
+                Response::Error
+            }
+        }
+    }
+
+    fn is_gamer_turn(&mut self, session_id: SessionIdType, gamer_id: GamerIdType) -> Response {
+        match self.sessions.get(&session_id) {
+            Some(session) => Response::OkWithBool(session.is_gamer_turn(gamer_id)),
+            None => {
+                error!("Missing session");
+                Response::Error
+            }
+        }
+    }
+
+    fn send_update(
+        &mut self,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+        update: Vec<u8>,
+    ) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        if let Err(reason) = session.check_payload_size(&update) {
+            return Response::ErrorWithReason(reason);
+        }
+
+        if !session.add_update(gamer_id, update) {
+            error!("Gamer is missing missing");
+            return Response::Error;
+        }
+
+        Response::Ok
+    }
+
+    fn get_previous_round_updates(&mut self, session_id: SessionIdType) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        Response::OkWithPreviousRoundUpdates(session.previous_round_updates())
+    }
+
+    fn send_message(&mut self, session_id: SessionIdType, message: Message) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        if let Err(reason) = session.save_message(message) {
+            return Response::ErrorWithReason(reason);
+        }
+
+        Response::Ok
+    }
+
+    fn fetch_all_messages(&mut self, session_id: SessionIdType, gamer_id: GamerIdType) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        Response::OkWithMessages(session.gamer_messages(gamer_id))
+    }
+
+    fn next_gamer(&mut self, session_id: SessionIdType) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        if !session.next_gamer() {
+            return Response::Error;
+        }
+
+        Response::Ok
+    }
+
+    fn ack_messages(
+        &mut self,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+        seq: u64,
+    ) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        if !session.ack_gamer_messages(gamer_id, seq) {
+            return Response::Error;
+        }
+
+        Response::Ok
+    }
+
+    fn assign_team(
+        &mut self,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+        team: Option<TeamIdType>,
+    ) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        if !session.assign_team(gamer_id, team) {
+            return Response::Error;
+        }
+
+        Response::Ok
+    }
+
+    fn get_teams(&mut self, session_id: SessionIdType) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        Response::OkWithTeams(session.teams())
+    }
+
+    fn get_channel_token(&mut self, session_id: SessionIdType, gamer_id: GamerIdType) -> Response {
+        let Some(session) = self.session_mut(&session_id) else {
+            return Response::Error;
+        };
+
+        match session.channel_token(&gamer_id) {
+            Some(token) => Response::OkWithToken(token),
+            None => {
+                error!("Gamer is missing");
+                Response::Error
+            }
+        }
+    }
+
+    fn session_mut(&mut self, session_id: &SessionIdType) -> Option<&mut GameSession> {
+        let session = self.sessions.get_mut(session_id);
+        if session.is_none() {
+            error!("Session is missing");
+        }
+
+        session
+    }
+}
@@ -0,0 +1,153 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use minignetcommon::{MessageLimits, QueueOverflowPolicy};
+
+use crate::rate_limit::RateLimit;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConnectionLimits {
+    pub read_timeout: Duration,
+    pub idle_timeout: Duration,
+    pub max_request_size: usize,
+    pub keep_alive_timeout: Duration,
+    pub compression_threshold: usize,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct ServerConfig {
+    /// Address the TCP listener binds to.
+    #[arg(long, default_value = "0.0.0.0:8888")]
+    pub addr: String,
+
+    /// Do not listen on TCP, eg. when serving only a Unix domain socket.
+    #[arg(long)]
+    pub no_tcp: bool,
+
+    /// Path of a Unix domain socket to listen on as well, for clients on the same machine.
+    #[cfg(unix)]
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Address a WebSocket listener binds to, for browser clients. Disabled when not set.
+    #[arg(long)]
+    pub ws_addr: Option<String>,
+
+    /// Address the HTTP gateway binds to, mapping REST routes to operations. Disabled when not
+    /// set.
+    #[arg(long)]
+    pub http_addr: Option<String>,
+
+    /// Address a UDP socket binds to, relaying unreliable state snapshots between the gamers of
+    /// a session. Disabled when not set.
+    #[arg(long)]
+    pub udp_addr: Option<String>,
+
+    /// PEM file with the certificate chain, enabling TLS on the TCP, WebSocket and HTTP
+    /// listeners.
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of the TLS certificate.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Maximum number of unacknowledged messages kept per gamer.
+    #[arg(long, default_value_t = 1024)]
+    pub max_queued_messages: usize,
+
+    /// Maximum size in bytes of a message or update payload.
+    #[arg(long, default_value_t = 64 * 1024)]
+    pub max_payload_size: usize,
+
+    /// What to do when a recipient's message queue is full: reject-sender or drop-oldest.
+    #[arg(long, default_value_t = QueueOverflowPolicy::RejectSender)]
+    pub queue_overflow_policy: QueueOverflowPolicy,
+
+    /// Maximum payload bytes returned by one message fetch, the rest follows once the fetched
+    /// messages are acknowledged.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub max_fetch_size: usize,
+
+    /// Maximum number of connections handled at the same time.
+    #[arg(long, default_value_t = 1024)]
+    pub max_connections: usize,
+
+    /// Requests and UDP datagrams per second allowed from one remote IP, 0 disables the limit.
+    #[arg(long, default_value_t = 100.0)]
+    pub ip_rate_limit: f64,
+
+    /// Requests one remote IP can burst above its rate limit.
+    #[arg(long, default_value_t = 200.0)]
+    pub ip_rate_burst: f64,
+
+    /// Requests and UDP datagrams per second allowed from one gamer, 0 disables the limit.
+    #[arg(long, default_value_t = 50.0)]
+    pub gamer_rate_limit: f64,
+
+    /// Requests one gamer can burst above its rate limit.
+    #[arg(long, default_value_t = 100.0)]
+    pub gamer_rate_burst: f64,
+
+    /// Milliseconds a client has to send its complete request.
+    #[arg(long, default_value_t = 10_000)]
+    pub read_timeout_ms: u64,
+
+    /// Milliseconds a connection may stay silent while sending its request.
+    #[arg(long, default_value_t = 5_000)]
+    pub idle_timeout_ms: u64,
+
+    /// Maximum size in bytes of an encoded request.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub max_request_size: usize,
+
+    /// Milliseconds a framed connection may stay idle between requests before it is closed, and
+    /// a UDP peer may stay silent before it is forgotten.
+    #[arg(long, default_value_t = 60_000)]
+    pub keep_alive_timeout_ms: u64,
+
+    /// Responses of at least this many bytes are compressed on framed connections where the
+    /// client offered a compression.
+    #[arg(long, default_value_t = 1024)]
+    pub compression_threshold: usize,
+}
+
+impl ServerConfig {
+    pub(crate) fn message_limits(&self) -> MessageLimits {
+        MessageLimits {
+            max_queued_messages: self.max_queued_messages,
+            max_payload_size: self.max_payload_size,
+            overflow_policy: self.queue_overflow_policy,
+            max_fetch_size: self.max_fetch_size,
+        }
+    }
+
+    pub(crate) fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            read_timeout: Duration::from_millis(self.read_timeout_ms),
+            idle_timeout: Duration::from_millis(self.idle_timeout_ms),
+            max_request_size: self.max_request_size,
+            keep_alive_timeout: Duration::from_millis(self.keep_alive_timeout_ms),
+            compression_threshold: self.compression_threshold,
+        }
+    }
+
+    pub(crate) fn ip_rate_limit(&self) -> Option<RateLimit> {
+        Self::rate_limit(self.ip_rate_limit, self.ip_rate_burst)
+    }
+
+    pub(crate) fn gamer_rate_limit(&self) -> Option<RateLimit> {
+        Self::rate_limit(self.gamer_rate_limit, self.gamer_rate_burst)
+    }
+
+    fn rate_limit(per_second: f64, burst: f64) -> Option<RateLimit> {
+        if per_second <= 0.0 {
+            return None;
+        }
+
+        Some(RateLimit {
+            per_second,
+            burst: burst.max(1.0),
+        })
+    }
+}
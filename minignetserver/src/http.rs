@@ -0,0 +1,406 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{
+        ConnectInfo, DefaultBodyLimit, FromRequestParts, Path, Query, Request, State,
+        rejection::ExtensionRejection,
+    },
+    http::{StatusCode, request::Parts},
+    middleware::{self, Next},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use log::warn;
+use minignetcommon::{
+    ErrorReason, GamerIdType, Message, Operation, Response, SessionIdType, TeamIdType,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::timeout,
+};
+use tower::ServiceExt;
+
+use crate::{MGNServer, PeerAddr, RequestTag, ServerState, config::ConnectionLimits};
+
+/// Header carrying the client trace id, see `RequestEnvelope::trace_id`.
+const TRACE_ID_HEADER: &str = "x-trace-id";
+
+type HttpResponse = (StatusCode, Json<Response>);
+
+/// Routes of the HTTP gateway. Every route maps to one `Operation` and answers with the JSON
+/// `Response`, as on a JSON lines connection.
+pub(crate) fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/sessions/{session_id}/join", post(join_session))
+        .route("/sessions/{session_id}/reset", post(reset_session))
+        .route("/sessions/{session_id}/start", post(start_session))
+        .route("/sessions/{session_id}/end", post(end_session))
+        .route("/sessions/{session_id}/next-gamer", post(next_gamer))
+        .route("/sessions/{session_id}/game-on", get(is_game_on))
+        .route("/sessions/{session_id}/turn", get(is_gamer_turn))
+        .route(
+            "/sessions/{session_id}/updates",
+            get(previous_round_updates).post(send_update),
+        )
+        .route(
+            "/sessions/{session_id}/messages",
+            get(fetch_messages).post(send_message),
+        )
+        .route("/sessions/{session_id}/messages/ack", post(ack_messages))
+        .route(
+            "/sessions/{session_id}/team",
+            post(assign_team).delete(leave_team),
+        )
+        .route("/sessions/{session_id}/teams", get(get_teams))
+        .layer(DefaultBodyLimit::max(
+            state.connection_limits.max_request_size,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.connection_limits.read_timeout,
+            within_read_timeout,
+        ))
+        .with_state(state)
+}
+
+/// Serves HTTP/1 requests on one accepted connection. The request head has to arrive within
+/// `--read-timeout-ms`, which also closes connections idle between requests.
+pub(crate) async fn serve_connection<S>(
+    stream: S,
+    remote_addr: SocketAddr,
+    router: Router,
+    connection_limits: ConnectionLimits,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(remote_addr));
+        router.clone().oneshot(request)
+    });
+
+    let connection = http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(connection_limits.read_timeout)
+        .serve_connection(TokioIo::new(stream), service);
+    if let Err(err) = connection.await {
+        warn!("HTTP connection from {} failed: {:?}", remote_addr, err);
+    }
+}
+
+/// Answers `ErrorReason::Timeout` when a request, including reading its body, takes longer
+/// than `--read-timeout-ms`.
+async fn within_read_timeout(
+    State(read_timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> axum::response::Response {
+    match timeout(read_timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let response = Response::ErrorWithReason(ErrorReason::Timeout);
+            (status(&response), Json(response)).into_response()
+        }
+    }
+}
+
+/// What a route needs to hand its operation to the server.
+struct Call {
+    state: ServerState,
+    remote_addr: SocketAddr,
+    trace_id: Option<String>,
+}
+
+impl FromRequestParts<ServerState> for Call {
+    type Rejection = ExtensionRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(remote_addr) = ConnectInfo::from_request_parts(parts, state).await?;
+
+        Ok(Self {
+            state: state.clone(),
+            remote_addr,
+            trace_id: parts
+                .headers
+                .get(TRACE_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+impl Call {
+    async fn handle(self, operation: Operation) -> HttpResponse {
+        let tag = RequestTag {
+            id: None,
+            trace_id: self.trace_id,
+        };
+        let response = MGNServer::handle_operation(
+            operation,
+            &tag,
+            PeerAddr::Tcp(self.remote_addr),
+            &self.state,
+        )
+        .await;
+
+        (status(&response), Json(response))
+    }
+}
+
+fn status(response: &Response) -> StatusCode {
+    match response {
+        Response::Error => StatusCode::BAD_REQUEST,
+        Response::ErrorWithReason(reason) => match reason {
+            ErrorReason::UnknownRecipient => StatusCode::NOT_FOUND,
+            ErrorReason::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorReason::PayloadTooLarge | ErrorReason::RequestTooLarge => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            ErrorReason::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorReason::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorReason::IncompatibleVersion | ErrorReason::UnsupportedRequest => {
+                StatusCode::BAD_REQUEST
+            }
+        },
+        _ => StatusCode::OK,
+    }
+}
+
+#[derive(Deserialize)]
+struct GamerQuery {
+    gamer: GamerIdType,
+}
+
+#[derive(Deserialize)]
+struct AckQuery {
+    gamer: GamerIdType,
+    seq: u64,
+}
+
+#[derive(Deserialize)]
+struct TeamQuery {
+    gamer: GamerIdType,
+    team: TeamIdType,
+}
+
+async fn join_session(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<GamerQuery>,
+) -> HttpResponse {
+    call.handle(Operation::JoinSession(session_id, query.gamer))
+        .await
+}
+
+async fn reset_session(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::ResetSession(session_id)).await
+}
+
+async fn start_session(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::StartSession(session_id)).await
+}
+
+async fn end_session(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::EndSession(session_id)).await
+}
+
+async fn next_gamer(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::NextGamer(session_id)).await
+}
+
+async fn is_game_on(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::IsGameOn(session_id)).await
+}
+
+async fn is_gamer_turn(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<GamerQuery>,
+) -> HttpResponse {
+    call.handle(Operation::IsGamerTurn(session_id, query.gamer))
+        .await
+}
+
+/// The request body is the raw update.
+async fn send_update(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<GamerQuery>,
+    update: Bytes,
+) -> HttpResponse {
+    call.handle(Operation::SendUpdate(
+        session_id,
+        query.gamer,
+        update.to_vec(),
+    ))
+    .await
+}
+
+async fn previous_round_updates(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::GetPreviousRoundUpdates(session_id))
+        .await
+}
+
+/// The request body is a JSON `Message`.
+async fn send_message(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Json(message): Json<Message>,
+) -> HttpResponse {
+    call.handle(Operation::SendMessage(session_id, message))
+        .await
+}
+
+async fn fetch_messages(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<GamerQuery>,
+) -> HttpResponse {
+    call.handle(Operation::FetchAllMessages(session_id, query.gamer))
+        .await
+}
+
+async fn ack_messages(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<AckQuery>,
+) -> HttpResponse {
+    call.handle(Operation::AckMessages(session_id, query.gamer, query.seq))
+        .await
+}
+
+async fn assign_team(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<TeamQuery>,
+) -> HttpResponse {
+    call.handle(Operation::AssignTeam(session_id, query.gamer, query.team))
+        .await
+}
+
+async fn leave_team(
+    call: Call,
+    Path(session_id): Path<SessionIdType>,
+    Query(query): Query<GamerQuery>,
+) -> HttpResponse {
+    call.handle(Operation::LeaveTeam(session_id, query.gamer))
+        .await
+}
+
+async fn get_teams(call: Call, Path(session_id): Path<SessionIdType>) -> HttpResponse {
+    call.handle(Operation::GetTeams(session_id)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, to_bytes};
+    use clap::Parser;
+    use minignetcommon::{MessageAddress, from_json_line, to_json_line};
+
+    use super::*;
+    use crate::config::ServerConfig;
+
+    fn state(args: &[&str]) -> ServerState {
+        ServerState::new(&ServerConfig::parse_from(["minignet"].iter().chain(args)))
+    }
+
+    fn request(method: &str, uri: &str, body: Vec<u8>) -> Request {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+
+        request
+    }
+
+    async fn call(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: Vec<u8>,
+    ) -> (StatusCode, Response) {
+        let response = router
+            .clone()
+            .oneshot(request(method, uri, body))
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, from_json_line(&body).unwrap())
+    }
+
+    fn message(to: MessageAddress) -> Vec<u8> {
+        to_json_line(&Message::new("alice".to_string(), to, vec![1, 2, 3])).unwrap()
+    }
+
+    #[tokio::test]
+    async fn message_round_trip() {
+        let router = router(state(&[]));
+        for gamer in ["alice", "bob"] {
+            let uri = format!("/sessions/game/join?gamer={}", gamer);
+            assert!(matches!(
+                call(&router, "POST", &uri, vec![]).await,
+                (StatusCode::OK, Response::Ok)
+            ));
+        }
+
+        let sent = message(MessageAddress::One("bob".to_string()));
+        assert!(matches!(
+            call(&router, "POST", "/sessions/game/messages", sent).await,
+            (StatusCode::OK, Response::Ok)
+        ));
+
+        let (status, response) =
+            call(&router, "GET", "/sessions/game/messages?gamer=bob", vec![]).await;
+        assert_eq!(status, StatusCode::OK);
+        let Response::OkWithMessages(messages) = response else {
+            panic!("Expected messages, got {:?}", response);
+        };
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, vec![1, 2, 3]);
+
+        let uri = format!(
+            "/sessions/game/messages/ack?gamer=bob&seq={}",
+            messages[0].seq
+        );
+        assert!(matches!(
+            call(&router, "POST", &uri, vec![]).await,
+            (StatusCode::OK, Response::Ok)
+        ));
+        assert!(matches!(
+            call(&router, "GET", "/sessions/game/messages?gamer=bob", vec![]).await,
+            (StatusCode::OK, Response::OkWithMessages(messages)) if messages.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejected_calls_map_to_http_statuses() {
+        let router = router(state(&["--max-request-size", "256"]));
+        call(&router, "POST", "/sessions/game/join?gamer=alice", vec![]).await;
+
+        let sent = message(MessageAddress::One("nobody".to_string()));
+        assert!(matches!(
+            call(&router, "POST", "/sessions/game/messages", sent).await,
+            (
+                StatusCode::NOT_FOUND,
+                Response::ErrorWithReason(ErrorReason::UnknownRecipient)
+            )
+        ));
+
+        let update = request("POST", "/sessions/game/updates?gamer=alice", vec![0; 257]);
+        let response = router.clone().oneshot(update).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,571 +1,1031 @@
 extern crate log;
 extern crate pretty_env_logger;
 
-use std::{collections::HashMap, sync::Arc};
+mod config;
+mod http;
+mod rate_limit;
+mod tls;
+mod transport;
+mod udp;
+
+#[cfg(unix)]
+use std::path::Path;
+use std::{
+    fmt,
+    io::ErrorKind,
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use log::{error, info, trace};
-use minignetcommon::{GamerIdType, Message, MessageAddress, Operation, Response, SessionIdType};
+use clap::Parser;
+use config::{ConnectionLimits, ServerConfig};
+use futures_util::StreamExt;
+use log::{error, info, trace, warn};
+use minignetcommon::{
+    Compression, ErrorReason, FRAMED_PREAMBLE, FrameCodec, Hello, HelloResponse, JSON_START,
+    MIN_PROTOCOL_VERSION, Operation, PROTOCOL_VERSION, RequestEnvelope, Response, ResponseEnvelope,
+    WorldState, decode_untrusted_within, from_json_line, request_id, to_json_line,
+};
+use rate_limit::RateLimiters;
+use serde::Serialize;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream, tcp::WriteHalf},
-    sync::Mutex,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, UdpSocket},
+    sync::{Mutex, Semaphore, mpsc},
+    task::JoinSet,
+    time::{Instant, error::Elapsed, timeout, timeout_at},
 };
-
-#[derive(Debug, Default, Clone)]
-pub(crate) struct UserUpdate {
-    pub update: Vec<u8>,
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{accept_async_with_config, tungstenite::protocol::WebSocketConfig};
+use transport::{FrameRead, FrameWrite, LengthDelimited};
+use udp::UdpRelay;
+
+/// Requests of one framed connection handled at the same time, reading pauses above it.
+const MAX_IN_FLIGHT_REQUESTS: usize = 64;
+
+/// Optional protocol features this server supports, see `Hello::capabilities`.
+const SERVER_CAPABILITIES: &[&str] = &[
+    Compression::Lz4.capability(),
+    Compression::Zstd.capability(),
+];
+
+/// Where a connection comes from, for logs and per IP rate limits.
+#[derive(Debug, Clone, Copy)]
+enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Peers of a Unix domain socket are unnamed and not rate limited per IP.
+    Unix,
 }
 
-#[derive(Debug, Default)]
-pub(crate) struct UserState {
-    updates: Vec<UserUpdate>,
-    awaiting_messages: Vec<Message>,
+impl PeerAddr {
+    fn ip(&self) -> Option<IpAddr> {
+        match self {
+            PeerAddr::Tcp(addr) => Some(addr.ip()),
+            PeerAddr::Unix => None,
+        }
+    }
 }
 
-impl UserState {
-    pub(crate) fn add_update(&mut self, update: Vec<u8>) {
-        self.updates.push(UserUpdate { update });
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix => write!(f, "unix socket peer"),
+        }
     }
+}
 
-    pub(crate) fn reset(&mut self) {
-        self.updates.clear();
-    }
+/// Ties log lines to a client call: the framed request id and the client trace id, if any.
+#[derive(Debug, Default)]
+struct RequestTag {
+    id: Option<u64>,
+    trace_id: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum GameState {
-    Join,
-    Game,
-    Over,
+impl fmt::Display for RequestTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, " #{}", id)?;
+        }
+        if let Some(trace_id) = &self.trace_id {
+            write!(f, " [{}]", trace_id)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-pub(crate) struct GameSession {
-    user_states: HashMap<GamerIdType, UserState>,
-    sequence: Vec<GamerIdType>,
-    current_gamer_index: usize,
-    state: GameState,
+/// State shared by every connection, whichever listener accepted it.
+#[derive(Clone)]
+struct ServerState {
+    world_state: Arc<Mutex<WorldState>>,
+    rate_limiters: Arc<RateLimiters>,
+    connection_limits: ConnectionLimits,
 }
 
-impl GameSession {
-    pub(crate) fn new() -> Self {
+impl ServerState {
+    fn new(config: &ServerConfig) -> Self {
         Self {
-            user_states: HashMap::new(),
-            current_gamer_index: 0,
-            state: GameState::Join,
-            sequence: vec![],
+            world_state: Arc::new(Mutex::new(WorldState::new(config.message_limits()))),
+            rate_limiters: Arc::new(RateLimiters::new(
+                config.ip_rate_limit(),
+                config.gamer_rate_limit(),
+            )),
+            connection_limits: config.connection_limits(),
         }
     }
+}
 
-    pub(crate) fn join(&mut self, gamer_id: GamerIdType) {
-        if self.user_states.contains_key(&gamer_id) {
-            // When it already exists - consider signalling so the client can fetch the
-            // previous state (aka re-join).
-            return;
+pub(crate) struct MGNServer {
+    config: ServerConfig,
+}
+
+impl MGNServer {
+    pub(crate) fn new(config: ServerConfig) -> Self {
+        Self { config }
+    }
+
+    pub(crate) async fn run(&self) {
+        let state = ServerState::new(&self.config);
+        let connection_slots = Arc::new(Semaphore::new(self.config.max_connections));
+        let mut listeners = JoinSet::new();
+
+        let tls = match (&self.config.tls_cert, &self.config.tls_key) {
+            (Some(cert_path), Some(key_path)) => match tls::acceptor(cert_path, key_path) {
+                Ok(acceptor) => Some(acceptor),
+                Err(err) => {
+                    error!("Failed loading TLS certificate: {:?}", err);
+                    return;
+                }
+            },
+            _ => None,
+        };
+
+        if !self.config.no_tcp {
+            let listener = match TcpListener::bind(&self.config.addr).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!(
+                        "Failed binding TCP listener to {}: {:?}",
+                        self.config.addr, err
+                    );
+                    return;
+                }
+            };
+            info!("TCP listener bound to {}", self.config.addr);
+            listeners.spawn(MGNServer::accept_tcp(
+                listener,
+                tls.clone(),
+                state.clone(),
+                connection_slots.clone(),
+            ));
         }
 
-        self.user_states
-            .insert(gamer_id.clone(), UserState::default());
+        if let Some(ws_addr) = &self.config.ws_addr {
+            let listener = match TcpListener::bind(ws_addr).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!(
+                        "Failed binding WebSocket listener to {}: {:?}",
+                        ws_addr, err
+                    );
+                    return;
+                }
+            };
+            info!("WebSocket listener bound to {}", ws_addr);
+            listeners.spawn(MGNServer::accept_websockets(
+                listener,
+                tls.clone(),
+                state.clone(),
+                connection_slots.clone(),
+            ));
+        }
 
-        self.sequence.push(gamer_id);
-    }
+        if let Some(http_addr) = &self.config.http_addr {
+            let listener = match TcpListener::bind(http_addr).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Failed binding HTTP gateway to {}: {:?}", http_addr, err);
+                    return;
+                }
+            };
+            info!("HTTP gateway bound to {}", http_addr);
+            listeners.spawn(MGNServer::accept_http(
+                listener,
+                tls.clone(),
+                state.clone(),
+                connection_slots.clone(),
+            ));
+        }
 
-    pub(crate) fn is_gamer_turn(&self, gamer_id: GamerIdType) -> bool {
-        if self.state != GameState::Game {
-            return false;
+        if let Some(udp_addr) = &self.config.udp_addr {
+            let socket = match UdpSocket::bind(udp_addr).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    error!("Failed binding UDP socket to {}: {:?}", udp_addr, err);
+                    return;
+                }
+            };
+            info!("UDP socket bound to {}", udp_addr);
+            let relay = UdpRelay::new(
+                socket,
+                state.world_state.clone(),
+                state.rate_limiters.clone(),
+                state.connection_limits.keep_alive_timeout,
+            );
+            listeners.spawn(relay.run());
         }
 
-        self.sequence
-            .iter()
-            .position(|id| id == &gamer_id)
-            .map(|pos| pos == self.current_gamer_index)
-            .unwrap_or(false)
+        #[cfg(unix)]
+        if let Some(path) = &self.config.unix_socket {
+            if !MGNServer::remove_stale_socket(path) {
+                return;
+            }
+            let listener = match UnixListener::bind(path) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!(
+                        "Failed binding Unix socket listener to {:?}: {:?}",
+                        path, err
+                    );
+                    return;
+                }
+            };
+            info!("Unix socket listener bound to {:?}", path);
+            listeners.spawn(MGNServer::accept_unix(
+                listener,
+                state.clone(),
+                connection_slots.clone(),
+            ));
+        }
+
+        if listeners.is_empty() {
+            error!("No listener configured");
+            return;
+        }
+
+        while let Some(result) = listeners.join_next().await {
+            if let Err(err) = result {
+                error!("Listener failed: {:?}", err);
+            }
+        }
     }
 
-    pub(crate) fn is_game_on(&self) -> bool {
-        self.state == GameState::Game
+    async fn accept_tcp(
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+        state: ServerState,
+        connection_slots: Arc<Semaphore>,
+    ) {
+        loop {
+            let (socket, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("Failed accepting connection: {:?}", err);
+                    continue;
+                }
+            };
+
+            let remote_addr = PeerAddr::Tcp(remote_addr);
+            let _state = state.clone();
+            let _tls = tls.clone();
+            MGNServer::spawn_connection(&connection_slots, remote_addr, async move {
+                match _tls {
+                    Some(acceptor) => {
+                        let limits = _state.connection_limits;
+                        if let Some(stream) =
+                            MGNServer::accept_tls(&acceptor, socket, remote_addr, &limits).await
+                        {
+                            MGNServer::process(stream, remote_addr, _state).await;
+                        }
+                    }
+                    None => MGNServer::process(socket, remote_addr, _state).await,
+                }
+            });
+        }
     }
 
-    pub(crate) fn reset(&mut self) {
-        self.state = GameState::Join;
-        self.current_gamer_index = 0;
+    async fn accept_websockets(
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+        state: ServerState,
+        connection_slots: Arc<Semaphore>,
+    ) {
+        loop {
+            let (socket, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("Failed accepting WebSocket connection: {:?}", err);
+                    continue;
+                }
+            };
 
-        for (_, user_state) in self.user_states.iter_mut() {
-            user_state.reset();
+            let remote_addr = PeerAddr::Tcp(remote_addr);
+            let _state = state.clone();
+            let _tls = tls.clone();
+            MGNServer::spawn_connection(&connection_slots, remote_addr, async move {
+                match _tls {
+                    Some(acceptor) => {
+                        let limits = _state.connection_limits;
+                        if let Some(stream) =
+                            MGNServer::accept_tls(&acceptor, socket, remote_addr, &limits).await
+                        {
+                            MGNServer::process_websocket(stream, remote_addr, _state).await;
+                        }
+                    }
+                    None => MGNServer::process_websocket(socket, remote_addr, _state).await,
+                }
+            });
         }
     }
 
-    pub(crate) fn start(&mut self) {
-        if self.state == GameState::Join {
-            self.state = GameState::Game;
-            info!("Session has started");
-        } else {
-            error!("Starting a session that is not in JOIN state");
+    async fn accept_http(
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+        state: ServerState,
+        connection_slots: Arc<Semaphore>,
+    ) {
+        let router = http::router(state.clone());
+
+        loop {
+            let (socket, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("Failed accepting HTTP connection: {:?}", err);
+                    continue;
+                }
+            };
+
+            let limits = state.connection_limits;
+            let _router = router.clone();
+            let _tls = tls.clone();
+            MGNServer::spawn_connection(
+                &connection_slots,
+                PeerAddr::Tcp(remote_addr),
+                async move {
+                    match _tls {
+                        Some(acceptor) => {
+                            let peer_addr = PeerAddr::Tcp(remote_addr);
+                            if let Some(stream) =
+                                MGNServer::accept_tls(&acceptor, socket, peer_addr, &limits).await
+                            {
+                                http::serve_connection(stream, remote_addr, _router, limits).await;
+                            }
+                        }
+                        None => http::serve_connection(socket, remote_addr, _router, limits).await,
+                    }
+                },
+            );
         }
     }
 
-    pub(crate) fn end(&mut self) {
-        if self.state == GameState::Game {
-            self.state = GameState::Over;
-        } else {
-            error!("Ending a session that is not in GAME state");
+    #[cfg(unix)]
+    async fn accept_unix(
+        listener: UnixListener,
+        state: ServerState,
+        connection_slots: Arc<Semaphore>,
+    ) {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    error!("Failed accepting Unix socket connection: {:?}", err);
+                    continue;
+                }
+            };
+
+            let _state = state.clone();
+            MGNServer::spawn_connection(
+                &connection_slots,
+                PeerAddr::Unix,
+                MGNServer::process(socket, PeerAddr::Unix, _state),
+            );
         }
     }
 
-    pub(crate) fn add_update(&mut self, gamer_id: GamerIdType, update: Vec<u8>) -> bool {
-        match self.user_states.get_mut(&gamer_id) {
-            Some(user_state) => {
-                user_state.add_update(update);
-                true
+    /// A socket file left behind by a previous run makes binding fail. Returns false when a
+    /// running server still listens on it, so its clients are not taken over.
+    #[cfg(unix)]
+    fn remove_stale_socket(path: &Path) -> bool {
+        use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return true;
+        };
+        if !metadata.file_type().is_socket() {
+            return true;
+        }
+
+        match UnixStream::connect(path) {
+            Ok(_) => {
+                error!("Another server is listening on {:?}", path);
+                return false;
             }
-            None => {
-                error!("Gamer is missing missing");
-                false
+            Err(err) if err.kind() != ErrorKind::ConnectionRefused => {
+                error!("Failed checking socket {:?}: {:?}", path, err);
+                return false;
             }
+            Err(_) => {}
+        }
+
+        info!("Removing stale socket {:?}", path);
+        if let Err(err) = std::fs::remove_file(path) {
+            error!("Failed removing stale socket {:?}: {:?}", path, err);
         }
+        true
     }
 
-    pub(crate) fn save_message(&mut self, message: Message) {
-        match &message.to {
-            MessageAddress::All => {
-                for gamer_id in &self.sequence {
-                    if gamer_id == &message.from {
-                        continue;
-                    }
+    /// Serves the connection in its own task while a connection slot is free, drops it
+    /// otherwise.
+    fn spawn_connection<F>(connection_slots: &Arc<Semaphore>, remote_addr: PeerAddr, connection: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Ok(connection_slot) = connection_slots.clone().try_acquire_owned() else {
+            warn!("Connection limit reached, dropping {}", remote_addr);
+            return;
+        };
 
-                    self.user_states
-                        .get_mut(gamer_id)
-                        .expect("Missing user state")
-                        .awaiting_messages
-                        .push(message.clone());
-                }
+        tokio::spawn(async move {
+            connection.await;
+            drop(connection_slot);
+        });
+    }
+
+    /// Runs the TLS handshake, `None` when it failed or timed out.
+    async fn accept_tls<S>(
+        acceptor: &TlsAcceptor,
+        stream: S,
+        remote_addr: PeerAddr,
+        connection_limits: &ConnectionLimits,
+    ) -> Option<tokio_rustls::server::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match timeout(connection_limits.read_timeout, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => Some(stream),
+            Ok(Err(err)) => {
+                warn!("TLS handshake with {} failed: {:?}", remote_addr, err);
+                None
             }
-            MessageAddress::One(gamer_id) => {
-                self.user_states
-                    .get_mut(gamer_id)
-                    .expect("Missing user state")
-                    .awaiting_messages
-                    .push(message.clone());
+            Err(_) => {
+                warn!("TLS handshake with {} timed out", remote_addr);
+                None
             }
         }
     }
 
-    pub(crate) fn pop_gamer_messages(&mut self, gamer_id: GamerIdType) -> Vec<Message> {
-        self.user_states
-            .get_mut(&gamer_id)
-            .map(|user_state| {
-                let mut out_messages = vec![];
-                std::mem::swap(&mut out_messages, &mut user_state.awaiting_messages);
-                out_messages
-            })
-            .unwrap_or(vec![])
-    }
+    /// Serves a WebSocket connection. Every binary message carries one frame of the framed
+    /// protocol, there is no preamble.
+    async fn process_websocket<S>(stream: S, remote_addr: PeerAddr, state: ServerState)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(state.connection_limits.max_request_size));
+        let websocket = match timeout(
+            state.connection_limits.read_timeout,
+            accept_async_with_config(stream, Some(config)),
+        )
+        .await
+        {
+            Ok(Ok(websocket)) => websocket,
+            Ok(Err(err)) => {
+                warn!("WebSocket handshake with {} failed: {:?}", remote_addr, err);
+                return;
+            }
+            Err(_) => {
+                warn!("WebSocket handshake with {} timed out", remote_addr);
+                return;
+            }
+        };
 
-    pub(crate) fn next_gamer(&mut self) {
-        self.current_gamer_index = (self.current_gamer_index + 1) % self.sequence.len();
+        let (writer, reader) = websocket.split();
+        MGNServer::process_framed(reader, writer, remote_addr, state).await;
     }
-}
 
-#[derive(Debug, Default)]
-pub(crate) struct WorldState {
-    sessions: HashMap<SessionIdType, GameSession>,
-}
-
-impl WorldState {}
+    async fn process<S>(stream: S, remote_addr: PeerAddr, state: ServerState)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let connection_limits = state.connection_limits;
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let deadline = Instant::now().checked_add(connection_limits.read_timeout);
+
+        let mut first_byte = [0; 1];
+        let bytes = match MGNServer::read_within(
+            deadline,
+            connection_limits.idle_timeout,
+            reader.read(&mut first_byte),
+        )
+        .await
+        {
+            Ok(Ok(0)) => vec![],
+            Ok(Ok(_)) if first_byte[0] == FRAMED_PREAMBLE[0] => {
+                let mut preamble = [0; FRAMED_PREAMBLE.len() - 1];
+                match timeout(
+                    connection_limits.idle_timeout,
+                    reader.read_exact(&mut preamble),
+                )
+                .await
+                {
+                    Ok(Ok(_)) if preamble == FRAMED_PREAMBLE[1..] => {}
+                    _ => {
+                        warn!("Invalid preamble from {}, closing connection", remote_addr);
+                        return;
+                    }
+                }
 
-pub(crate) struct MGNServer {}
+                MGNServer::process_framed(
+                    LengthDelimited(reader),
+                    LengthDelimited(writer),
+                    remote_addr,
+                    state,
+                )
+                .await;
+                return;
+            }
+            Ok(Ok(_)) if first_byte[0] == JSON_START => {
+                MGNServer::process_json(reader, writer, first_byte[0], remote_addr, state).await;
+                return;
+            }
+            // An unversioned one-shot request, as sent by clients built before the handshake.
+            Ok(Ok(_)) => first_byte.to_vec(),
+            Ok(Err(err)) => {
+                error!("Error while reading: {:?}", err);
+                return;
+            }
+            Err(_) => {
+                warn!(
+                    "Rejecting request from {}: {:?}",
+                    remote_addr,
+                    ErrorReason::Timeout
+                );
+                MGNServer::reply_client(
+                    &mut writer,
+                    Response::ErrorWithReason(ErrorReason::Timeout),
+                )
+                .await;
+                MGNServer::shutdown(&mut writer).await;
+                return;
+            }
+        };
 
-impl MGNServer {
-    pub(crate) fn new() -> Self {
-        Self {}
-    }
+        let bytes =
+            match MGNServer::read_request(&mut reader, bytes, deadline, &connection_limits).await {
+                Ok(bytes) => bytes,
+                Err(Some(reason)) => {
+                    warn!("Rejecting request from {}: {:?}", remote_addr, reason);
+                    MGNServer::reply_client(&mut writer, Response::ErrorWithReason(reason)).await;
+                    MGNServer::shutdown(&mut writer).await;
+                    return;
+                }
+                Err(None) => return,
+            };
 
-    pub(crate) async fn run(&self) {
-        let world_state: Arc<Mutex<WorldState>> = Arc::new(Mutex::new(WorldState::default()));
-        let listener = TcpListener::bind("0.0.0.0:8888").await.unwrap();
+        let op: Result<(Operation, usize), bincode::error::DecodeError> =
+            decode_untrusted_within(&bytes[..], state.connection_limits.max_request_size);
 
-        loop {
-            let (socket, _) = listener.accept().await.unwrap();
-            let _world_state = world_state.clone();
-            tokio::spawn(async move { MGNServer::process(socket, _world_state).await });
+        match op {
+            Ok((operation, ..)) => {
+                let response = MGNServer::handle_operation(
+                    operation,
+                    &RequestTag::default(),
+                    remote_addr,
+                    &state,
+                )
+                .await;
+                MGNServer::reply_client(&mut writer, response).await;
+            }
+            Err(err) => {
+                error!("Failed decoding input: {:?}", err);
+                MGNServer::reply_client(&mut writer, Response::Error).await;
+            }
         }
+
+        MGNServer::shutdown(&mut writer).await;
     }
 
-    async fn process(mut stream: TcpStream, world_state: Arc<Mutex<WorldState>>) {
-        let mut bytes: Vec<u8> = vec![];
-        let (mut reader, mut writer) = stream.split();
-        let mut buf: [u8; 1024] = [0; 1024];
+    /// Serves a JSON lines connection, see `JSON_START`. Requests are handled one at a time and
+    /// answered in order, so the connection can be driven by hand, eg. with netcat. The first
+    /// line may be a `Hello`, answered with a `HelloResponse` line, to check the version.
+    async fn process_json<R, W>(
+        reader: R,
+        mut writer: W,
+        first_byte: u8,
+        remote_addr: PeerAddr,
+        state: ServerState,
+    ) where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let connection_limits = state.connection_limits;
+        info!("JSON connection from {}", remote_addr);
+
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![first_byte];
+        let mut expects_hello = true;
 
         loop {
-            match reader.read(&mut buf).await {
-                Ok(size) => {
-                    if size == 0 {
-                        trace!("Connection closed");
-                        break;
-                    }
-
-                    bytes.extend_from_slice(&buf[0..size]);
-                    trace!("Received {} bytes", size);
-                }
-                Err(err) => {
+            let mut limited = (&mut reader).take(connection_limits.max_request_size as u64);
+            let read = limited.read_until(b'\n', &mut line);
+            match timeout(connection_limits.keep_alive_timeout, read).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => {
                     error!("Error while reading: {:?}", err);
-                    return;
+                    break;
+                }
+                Err(_) => {
+                    info!("Closing idle connection from {}", remote_addr);
+                    break;
                 }
             }
-        }
 
-        let op: Result<(Operation, usize), bincode::error::DecodeError> =
-            bincode::decode_from_slice(&bytes[..], bincode::config::standard());
+            // Without a line break the client either closed the connection or sent too much.
+            let is_complete = line.ends_with(b"\n");
+            if !is_complete && line.len() >= connection_limits.max_request_size {
+                let reason = ErrorReason::RequestTooLarge;
+                warn!("Rejecting request from {}: {:?}", remote_addr, reason);
+                MGNServer::reply_json(&mut writer, Response::ErrorWithReason(reason)).await;
+                break;
+            }
 
-        match op {
-            Ok((operation, ..)) => {
-                info!("Received operation: {:?}", &operation);
-
-                match operation {
-                    Operation::JoinSession(session_id, gamer_id) => {
-                        MGNServer::process_join_session(
-                            &mut writer,
-                            session_id,
-                            gamer_id,
-                            world_state,
-                        )
-                        .await;
-                    }
-                    Operation::ResetSession(session_id) => {
-                        MGNServer::process_reset_session(&mut writer, session_id, world_state)
-                            .await;
-                    }
-                    Operation::StartSession(session_id) => {
-                        MGNServer::process_start_session(&mut writer, session_id, world_state)
-                            .await;
-                    }
-                    Operation::EndSession(session_id) => {
-                        MGNServer::process_end_session(&mut writer, session_id, world_state).await;
-                    }
-                    Operation::IsGamerTurn(session_id, gamer_id) => {
-                        MGNServer::process_is_gamer_turn(
-                            &mut writer,
-                            session_id,
-                            gamer_id,
-                            world_state,
-                        )
-                        .await;
-                    }
-                    Operation::IsGameOn(session_id) => {
-                        MGNServer::process_is_game_on(&mut writer, session_id, world_state).await;
-                    }
-                    Operation::SendUpdate(session_id, gamer_id, update) => {
-                        MGNServer::process_send_update(
-                            &mut writer,
-                            session_id,
-                            gamer_id,
-                            update,
-                            world_state,
-                        )
-                        .await;
-                    }
-                    Operation::GetPreviousRoundUpdates(session_id) => {
-                        MGNServer::process_get_previous_round_updates(
-                            &mut writer,
-                            session_id,
-                            world_state,
-                        )
-                        .await;
-                    }
-                    Operation::SendMessage(session_id, message) => {
-                        MGNServer::process_send_message(
-                            &mut writer,
-                            session_id,
-                            message,
-                            world_state,
+            let is_first_line = mem::replace(&mut expects_hello, false);
+            if is_first_line && let Ok(hello) = from_json_line::<Hello>(&line) {
+                // Compression does not apply to JSON lines, so no capability is supported.
+                let (response, capabilities) = MGNServer::answer_hello(hello, &[], remote_addr);
+                if !MGNServer::reply_json(&mut writer, response).await || capabilities.is_none() {
+                    break;
+                }
+            } else if !line.trim_ascii().is_empty() {
+                let response = match from_json_line(&line) {
+                    Ok(operation) => {
+                        MGNServer::handle_operation(
+                            operation,
+                            &RequestTag::default(),
+                            remote_addr,
+                            &state,
                         )
-                        .await;
+                        .await
                     }
-                    Operation::FetchAllMessages(session_id, gamer_id) => {
-                        MGNServer::process_fetch_all_messages(
-                            &mut writer,
-                            session_id,
-                            gamer_id,
-                            world_state,
-                        )
-                        .await;
+                    // Valid JSON of an unknown shape, eg. an operation of a newer client.
+                    Err(err) if err.is_data() => {
+                        warn!("Unsupported request from {}: {}", remote_addr, err);
+                        Response::ErrorWithReason(ErrorReason::UnsupportedRequest)
                     }
-                    Operation::NextGamer(session_id) => {
-                        MGNServer::process_next_gamer(&mut writer, session_id, world_state).await;
+                    Err(err) => {
+                        warn!("Failed decoding JSON from {}: {}", remote_addr, err);
+                        Response::Error
                     }
                 };
-            }
-            Err(err) => {
-                error!("Failed decoding input: {:?}", err);
-
-                let err_encoded =
-                    bincode::encode_to_vec(Response::Error, bincode::config::standard())
-                        .expect("Failed encoding error");
 
-                if let Err(err) = writer.write(&err_encoded[..]).await {
-                    error!("Failed responding error: {:?}", err);
+                if !MGNServer::reply_json(&mut writer, response).await {
+                    break;
                 }
             }
-        }
 
-        if let Err(err) = writer.shutdown().await {
-            error!("Failed to shut down writer: {:?}", err);
+            if !is_complete {
+                trace!("Connection closed");
+                break;
+            }
+            line.clear();
         }
-    }
 
-    async fn reply_client(writer: &mut WriteHalf<'_>, response: Response) {
-        let encoded = bincode::encode_to_vec(&response, bincode::config::standard())
-            .expect(&format!("Failed encoding response message: {:?}", response));
-        if let Err(err) = writer.write(&encoded[..]).await {
-            error!("Failed responding to client: {:?}", err);
-        }
+        MGNServer::shutdown(&mut writer).await;
     }
 
-    async fn process_join_session(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        gamer_id: GamerIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        {
-            let mut state = world_state.lock().await;
-            let session = state
-                .sessions
-                .entry(session_id.clone())
-                .or_insert(GameSession::new());
-
-            session.join(gamer_id);
-        }
-
-        MGNServer::reply_client(writer, Response::Ok).await
-    }
+    /// Serves a framed connection. Every request is handled in its own task and answered as
+    /// soon as it is done, so a client can multiplex many calls.
+    async fn process_framed<R, W>(
+        mut reader: R,
+        mut writer: W,
+        remote_addr: PeerAddr,
+        state: ServerState,
+    ) where
+        R: FrameRead,
+        W: FrameWrite + Send + 'static,
+    {
+        let connection_limits = state.connection_limits;
+        let Some(capabilities) =
+            MGNServer::handshake(&mut reader, &mut writer, remote_addr, &connection_limits).await
+        else {
+            return;
+        };
+        info!(
+            "Framed connection from {}, capabilities: {:?}",
+            remote_addr, capabilities
+        );
+        let codec = FrameCodec::new(
+            Compression::negotiate(&capabilities),
+            connection_limits.compression_threshold,
+        );
+
+        let (responses, response_receiver) = mpsc::channel(MAX_IN_FLIGHT_REQUESTS);
+        let writer_task =
+            tokio::spawn(MGNServer::write_responses(writer, response_receiver, codec));
+        let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS));
 
-    async fn process_reset_session(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        {
-            let mut state = world_state.lock().await;
-            match state.sessions.get_mut(&session_id) {
-                Some(session) => session.reset(),
-                None => {
-                    error!("Session {:?}, it does not exist", session_id);
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
+        loop {
+            let frame = match timeout(
+                connection_limits.keep_alive_timeout,
+                reader.read_frame(connection_limits.max_request_size),
+            )
+            .await
+            {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) => {
+                    trace!("Connection closed");
+                    break;
+                }
+                Ok(Err(err)) if err.kind() == ErrorKind::InvalidData => {
+                    let reason = ErrorReason::RequestTooLarge;
+                    warn!("Rejecting request from {}: {:?}", remote_addr, reason);
+                    let response = ResponseEnvelope {
+                        id: 0,
+                        trace_id: None,
+                        response: Response::ErrorWithReason(reason),
+                    };
+                    let _ = responses.send(response).await;
+                    break;
+                }
+                Ok(Err(err)) => {
+                    error!("Error while reading: {:?}", err);
+                    break;
+                }
+                Err(_) => {
+                    info!("Closing idle connection from {}", remote_addr);
+                    break;
                 }
             };
-        }
-
-        MGNServer::reply_client(writer, Response::Ok).await
-    }
 
-    async fn process_start_session(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        {
-            let mut state = world_state.lock().await;
-            match state.sessions.get_mut(&session_id) {
-                Some(session) => session.start(),
-                None => {
-                    error!("Cannot start session {:?}, it does not exist", session_id);
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
+            let frame = match codec.decode(frame, connection_limits.max_request_size) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    error!("Failed decompressing input: {:?}", err);
+                    break;
                 }
             };
-        }
 
-        MGNServer::reply_client(writer, Response::Ok).await
-    }
+            let request: RequestEnvelope =
+                match decode_untrusted_within(&frame[..], connection_limits.max_request_size) {
+                    Ok((request, ..)) => request,
+                    // Eg. an operation of a newer client, the connection stays usable.
+                    Err(err) => {
+                        let Some(id) = request_id(&frame) else {
+                            error!("Failed decoding input: {:?}", err);
+                            break;
+                        };
+                        warn!(
+                            "Failed decoding request #{} from {}: {:?}",
+                            id, remote_addr, err
+                        );
+                        let response = ResponseEnvelope {
+                            id,
+                            trace_id: None,
+                            response: Response::ErrorWithReason(ErrorReason::UnsupportedRequest),
+                        };
+                        if responses.send(response).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
 
-    async fn process_end_session(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        {
-            let mut state = world_state.lock().await;
-            match state.sessions.get_mut(&session_id) {
-                Some(session) => session.end(),
-                None => {
-                    error!("Cannot start session {:?}, it does not exist", session_id);
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
-                }
+            let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                break;
             };
+            let _responses = responses.clone();
+            let _state = state.clone();
+            tokio::spawn(async move {
+                let tag = RequestTag {
+                    id: Some(request.id),
+                    trace_id: request.trace_id,
+                };
+                let response =
+                    MGNServer::handle_operation(request.operation, &tag, remote_addr, &_state)
+                        .await;
+
+                let response = ResponseEnvelope {
+                    id: request.id,
+                    trace_id: tag.trace_id.clone(),
+                    response,
+                };
+                if _responses.send(response).await.is_err() {
+                    error!(
+                        "Connection to {} closed before responding to{}",
+                        remote_addr, tag
+                    );
+                }
+                drop(permit);
+            });
         }
 
-        MGNServer::reply_client(writer, Response::Ok).await
+        // The writer finishes once every in flight request has been answered.
+        drop(responses);
+        if let Err(err) = writer_task.await {
+            error!("Response writer failed: {:?}", err);
+        }
     }
 
-    async fn process_is_game_on(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let is_game_on;
+    /// Exchanges `Hello` frames with the client. Returns the capabilities in use on the
+    /// connection, or `None` when the client is incompatible or the handshake failed.
+    async fn handshake<R: FrameRead, W: FrameWrite>(
+        reader: &mut R,
+        writer: &mut W,
+        remote_addr: PeerAddr,
+        connection_limits: &ConnectionLimits,
+    ) -> Option<Vec<String>> {
+        let frame = match timeout(
+            connection_limits.idle_timeout,
+            reader.read_frame(connection_limits.max_request_size),
+        )
+        .await
         {
-            let mut state = world_state.lock().await;
-            is_game_on = match state.sessions.get_mut(&session_id) {
-                Some(session) => session.is_game_on(),
-                None => {
-                    error!("Missing session");
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
+            Ok(Ok(Some(frame))) => frame,
+            Ok(Ok(None)) => return None,
+            Ok(Err(err)) => {
+                error!("Error while reading handshake: {:?}", err);
+                return None;
+            }
+            Err(_) => {
+                warn!("Handshake from {} timed out", remote_addr);
+                return None;
+            }
+        };
+
+        let hello: Hello =
+            match decode_untrusted_within(&frame[..], connection_limits.max_request_size) {
+                Ok((hello, ..)) => hello,
+                Err(err) => {
+                    error!("Failed decoding handshake: {:?}", err);
+                    return None;
                 }
             };
+
+        let (response, capabilities) =
+            MGNServer::answer_hello(hello, SERVER_CAPABILITIES, remote_addr);
+
+        let encoded = match bincode::encode_to_vec(&response, bincode::config::standard()) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                error!("Failed encoding handshake {:?}: {:?}", response, err);
+                return None;
+            }
+        };
+        if let Err(err) = writer.write_frame(encoded).await {
+            error!("Failed responding to handshake: {:?}", err);
+            return None;
+        }
+
+        if capabilities.is_none() {
+            writer.close().await;
         }
 
-        MGNServer::reply_client(writer, Response::OkWithBool(is_game_on)).await
+        capabilities
     }
 
-    async fn process_is_gamer_turn(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        gamer_id: GamerIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let is_gamer_turn;
-        {
-            let mut state = world_state.lock().await;
-            is_gamer_turn = match state.sessions.get_mut(&session_id) {
-                Some(session) => session.is_gamer_turn(gamer_id),
-                None => {
-                    error!("Missing session");
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
-                }
+    /// Answers a client `Hello`, keeping the capabilities both sides support. The capabilities
+    /// are `None` when the client is incompatible.
+    fn answer_hello(
+        hello: Hello,
+        supported: &[&str],
+        remote_addr: PeerAddr,
+    ) -> (HelloResponse, Option<Vec<String>>) {
+        if !hello.is_compatible() {
+            warn!(
+                "Rejecting {}, protocol version {} is older than {}",
+                remote_addr, hello.protocol_version, MIN_PROTOCOL_VERSION
+            );
+            let response = HelloResponse::Rejected {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
             };
+            return (response, None);
         }
 
-        MGNServer::reply_client(writer, Response::OkWithBool(is_gamer_turn)).await
+        let capabilities: Vec<String> = hello
+            .capabilities
+            .into_iter()
+            .filter(|capability| supported.contains(&capability.as_str()))
+            .collect();
+        let response = HelloResponse::Accepted(Hello::new(capabilities.clone()));
+
+        (response, Some(capabilities))
     }
 
-    async fn process_send_update(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        gamer_id: GamerIdType,
-        update: Vec<u8>,
-        world_state: Arc<Mutex<WorldState>>,
+    async fn write_responses<W: FrameWrite>(
+        mut writer: W,
+        mut responses: mpsc::Receiver<ResponseEnvelope>,
+        codec: FrameCodec,
     ) {
-        {
-            let mut state = world_state.lock().await;
-            let session = match state.sessions.get_mut(&session_id) {
-                Some(session) => session,
-                None => {
-                    error!("Session is missing");
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
+        while let Some(response) = responses.recv().await {
+            let encoded = match bincode::encode_to_vec(&response, bincode::config::standard()) {
+                Ok(encoded) => encoded,
+                Err(err) => {
+                    error!("Failed encoding response message {:?}: {:?}", response, err);
+                    continue;
                 }
             };
-
-            if !session.add_update(gamer_id, update) {
-                error!("Gamer is missing missing");
-                MGNServer::reply_client(writer, Response::Error).await;
+            if let Err(err) = writer.write_frame(codec.encode(encoded)).await {
+                error!("Failed responding to client: {:?}", err);
                 return;
             }
         }
 
-        MGNServer::reply_client(writer, Response::Ok).await
+        writer.close().await;
     }
 
-    async fn process_get_previous_round_updates(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let mut previous_round_updates = HashMap::new();
-
-        {
-            let mut state = world_state.lock().await;
-            let session = match state.sessions.get_mut(&session_id) {
-                Some(session) => session,
-                None => {
-                    error!("Session is missing");
-                    MGNServer::reply_client(writer, Response::Error).await;
-                    return;
-                }
-            };
-
-            for (gamer_id, user_state) in session.user_states.iter() {
-                previous_round_updates.insert(
-                    gamer_id.clone(),
-                    user_state
-                        .updates
-                        .last()
-                        .map(|user_update| user_update.update.clone()),
-                );
-            }
+    async fn handle_operation(
+        operation: Operation,
+        tag: &RequestTag,
+        remote_addr: PeerAddr,
+        state: &ServerState,
+    ) -> Response {
+        info!("Received operation{}: {:?}", tag, &operation);
+
+        let gamer = operation
+            .gamer_id()
+            .map(|gamer_id| (operation.session_id(), gamer_id));
+        if !state.rate_limiters.check(remote_addr.ip(), gamer).await {
+            warn!("Rate limit exceeded by {}{}", remote_addr, tag);
+            return Response::ErrorWithReason(ErrorReason::RateLimited);
         }
 
-        MGNServer::reply_client(
-            writer,
-            Response::OkWithPreviousRoundUpdates(previous_round_updates),
-        )
-        .await
+        state.world_state.lock().await.handle(operation)
     }
 
-    async fn process_send_message(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        message: Message,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let mut state = world_state.lock().await;
-        let session = match state.sessions.get_mut(&session_id) {
-            Some(session) => session,
-            None => {
-                error!("Session is missing");
-                MGNServer::reply_client(writer, Response::Error).await;
-                return;
+    /// Reads the request until the client shuts down its write half, starting from the bytes
+    /// already read. Fails with the reason to report to the client when the request is too slow
+    /// or too large, or with `None` when the connection broke.
+    async fn read_request<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        mut bytes: Vec<u8>,
+        deadline: Option<Instant>,
+        connection_limits: &ConnectionLimits,
+    ) -> Result<Vec<u8>, Option<ErrorReason>> {
+        let mut buf: [u8; 1024] = [0; 1024];
+
+        loop {
+            let read = reader.read(&mut buf);
+
+            match MGNServer::read_within(deadline, connection_limits.idle_timeout, read).await {
+                Ok(Ok(size)) => {
+                    if size == 0 {
+                        trace!("Connection closed");
+                        return Ok(bytes);
+                    }
+
+                    if bytes.len() + size > connection_limits.max_request_size {
+                        return Err(Some(ErrorReason::RequestTooLarge));
+                    }
+
+                    bytes.extend_from_slice(&buf[0..size]);
+                    trace!("Received {} bytes", size);
+                }
+                Ok(Err(err)) => {
+                    error!("Error while reading: {:?}", err);
+                    return Err(None);
+                }
+                Err(_) => return Err(Some(ErrorReason::Timeout)),
             }
-        };
+        }
+    }
 
-        session.save_message(message);
+    /// Runs the read until the earlier of the request deadline and the idle timeout. A deadline
+    /// too far away to be represented, eg. with a huge `--read-timeout-ms`, is no deadline.
+    async fn read_within<F: Future>(
+        deadline: Option<Instant>,
+        idle_timeout: Duration,
+        read: F,
+    ) -> Result<F::Output, Elapsed> {
+        let idle_deadline = Instant::now().checked_add(idle_timeout);
+        match deadline.into_iter().chain(idle_deadline).min() {
+            Some(deadline) => timeout_at(deadline, read).await,
+            None => Ok(read.await),
+        }
+    }
 
-        MGNServer::reply_client(writer, Response::Ok).await
+    async fn shutdown<W: AsyncWrite + Unpin>(writer: &mut W) {
+        if let Err(err) = writer.shutdown().await {
+            error!("Failed to shut down writer: {:?}", err);
+        }
     }
 
-    async fn process_fetch_all_messages(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        gamer_id: GamerIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let mut state = world_state.lock().await;
-        let session = match state.sessions.get_mut(&session_id) {
-            Some(session) => session,
-            None => {
-                error!("Session is missing");
-                MGNServer::reply_client(writer, Response::Error).await;
-                return;
+    /// Returns whether the response was written.
+    async fn reply_json<W, T>(writer: &mut W, response: T) -> bool
+    where
+        W: AsyncWrite + Unpin,
+        T: Serialize + fmt::Debug,
+    {
+        let encoded = match to_json_line(&response) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                error!("Failed encoding response message {:?}: {:?}", response, err);
+                return false;
             }
         };
+        if let Err(err) = writer.write_all(&encoded).await {
+            error!("Failed responding to client: {:?}", err);
+            return false;
+        }
 
-        let messages = session.pop_gamer_messages(gamer_id);
-        MGNServer::reply_client(writer, Response::OkWithMessages(messages)).await
+        true
     }
 
-    async fn process_next_gamer(
-        writer: &mut WriteHalf<'_>,
-        session_id: SessionIdType,
-        world_state: Arc<Mutex<WorldState>>,
-    ) {
-        let mut state = world_state.lock().await;
-        let session = match state.sessions.get_mut(&session_id) {
-            Some(session) => session,
-            None => {
-                error!("Session is missing");
-                MGNServer::reply_client(writer, Response::Error).await;
+    async fn reply_client<W: AsyncWrite + Unpin>(writer: &mut W, response: Response) {
+        let encoded = match bincode::encode_to_vec(&response, bincode::config::standard()) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                error!("Failed encoding response message {:?}: {:?}", response, err);
                 return;
             }
         };
-
-        session.next_gamer();
-
-        MGNServer::reply_client(writer, Response::Ok).await
+        if let Err(err) = writer.write_all(&encoded[..]).await {
+            error!("Failed responding to client: {:?}", err);
+        }
     }
 }
 
@@ -574,6 +1034,181 @@ async fn main() {
     pretty_env_logger::init();
     info!("Server has started");
 
-    let server = MGNServer::new();
+    let server = MGNServer::new(ServerConfig::parse());
     server.run().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+    use serde::de::DeserializeOwned;
+    use tokio::io::{DuplexStream, ReadHalf, WriteHalf, duplex};
+
+    use super::*;
+
+    fn state() -> ServerState {
+        ServerState::new(&ServerConfig::parse_from(["minignet"]))
+    }
+
+    fn peer() -> PeerAddr {
+        PeerAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 4000)))
+    }
+
+    /// Client end of a framed connection, past the preamble.
+    fn framed_connection() -> LengthDelimited<DuplexStream> {
+        let (client, server) = duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(server);
+        tokio::spawn(MGNServer::process_framed(
+            LengthDelimited(reader),
+            LengthDelimited(writer),
+            peer(),
+            state(),
+        ));
+
+        LengthDelimited(client)
+    }
+
+    async fn send<T: Encode>(client: &mut LengthDelimited<DuplexStream>, value: T) {
+        let frame = bincode::encode_to_vec(value, bincode::config::standard()).unwrap();
+        client.write_frame(frame).await.unwrap();
+    }
+
+    async fn receive<T: Decode<()>>(client: &mut LengthDelimited<DuplexStream>) -> Option<T> {
+        let frame = client.read_frame(usize::MAX).await.unwrap()?;
+        Some(decode_untrusted_within(&frame, frame.len()).unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn huge_timeouts_mean_no_deadline() {
+        let forever = u64::MAX.to_string();
+        let config = ServerConfig::parse_from([
+            "minignet",
+            "--read-timeout-ms",
+            &forever,
+            "--idle-timeout-ms",
+            &forever,
+        ]);
+        let (mut client, server) = duplex(64 * 1024);
+        tokio::spawn(MGNServer::process(
+            server,
+            peer(),
+            ServerState::new(&config),
+        ));
+
+        let operation = Operation::JoinSession("game".to_string(), "alice".to_string());
+        let request = bincode::encode_to_vec(operation, bincode::config::standard()).unwrap();
+        client.write_all(&request).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        let (response, _): (Response, usize) =
+            decode_untrusted_within(&response, response.len()).unwrap();
+        assert!(matches!(response, Response::Ok));
+    }
+
+    #[tokio::test]
+    async fn older_protocol_version_is_rejected() {
+        let mut client = framed_connection();
+        let hello = Hello {
+            protocol_version: MIN_PROTOCOL_VERSION - 1,
+            capabilities: vec![],
+        };
+        send(&mut client, hello).await;
+
+        assert!(matches!(
+            receive(&mut client).await,
+            Some(HelloResponse::Rejected {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+            })
+        ));
+        assert!(receive::<ResponseEnvelope>(&mut client).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_operation_keeps_the_connection_open() {
+        let mut client = framed_connection();
+        send(&mut client, Hello::new(vec![])).await;
+        assert!(matches!(
+            receive(&mut client).await,
+            Some(HelloResponse::Accepted(_))
+        ));
+
+        // Request #7 with no trace id and an operation variant this build does not know.
+        send(&mut client, (7u64, None::<String>, 250u32)).await;
+        let response: ResponseEnvelope = receive(&mut client).await.unwrap();
+        assert_eq!(response.id, 7);
+        assert!(matches!(
+            response.response,
+            Response::ErrorWithReason(ErrorReason::UnsupportedRequest)
+        ));
+
+        let request = RequestEnvelope {
+            id: 8,
+            trace_id: None,
+            operation: Operation::JoinSession("game".to_string(), "alice".to_string()),
+        };
+        send(&mut client, request).await;
+        let response: ResponseEnvelope = receive(&mut client).await.unwrap();
+        assert_eq!(response.id, 8);
+        assert!(matches!(response.response, Response::Ok));
+    }
+
+    type JsonClient = (BufReader<ReadHalf<DuplexStream>>, WriteHalf<DuplexStream>);
+
+    /// Client end of a connection whose protocol the server detects from the first byte.
+    fn connection() -> JsonClient {
+        let (client, server) = duplex(64 * 1024);
+        tokio::spawn(MGNServer::process(server, peer(), state()));
+        let (reader, writer) = tokio::io::split(client);
+
+        (BufReader::new(reader), writer)
+    }
+
+    async fn json_call<T: DeserializeOwned>((reader, writer): &mut JsonClient, line: &str) -> T {
+        writer.write_all(line.as_bytes()).await.unwrap();
+        writer.write_all(b"\n").await.unwrap();
+
+        let mut response = vec![];
+        reader.read_until(b'\n', &mut response).await.unwrap();
+        from_json_line(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_lines_start_with_an_optional_hello() {
+        let mut client = connection();
+        let hello = String::from_utf8(to_json_line(&Hello::new(vec![])).unwrap()).unwrap();
+        assert!(matches!(
+            json_call(&mut client, hello.trim_end()).await,
+            HelloResponse::Accepted(hello) if hello.capabilities.is_empty()
+        ));
+        assert!(matches!(
+            json_call(&mut client, r#"{"JoinSession":["game","alice"]}"#).await,
+            Response::Ok
+        ));
+
+        let mut client = connection();
+        assert!(matches!(
+            json_call(&mut client, r#"{"JoinSession":["game","bob"]}"#).await,
+            Response::Ok
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_json_operation_is_unsupported() {
+        let mut client = connection();
+        assert!(matches!(
+            json_call(&mut client, r#"{"Teleport":["game","alice"]}"#).await,
+            Response::ErrorWithReason(ErrorReason::UnsupportedRequest)
+        ));
+        assert!(matches!(
+            json_call(&mut client, r#"{"JoinSession":["game""#).await,
+            Response::Error
+        ));
+        assert!(matches!(
+            json_call(&mut client, r#"{"JoinSession":["game","alice"]}"#).await,
+            Response::Ok
+        ));
+    }
+}
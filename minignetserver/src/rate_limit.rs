@@ -0,0 +1,195 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, time::Instant};
+
+use minignetcommon::{GamerIdType, SessionIdType};
+use tokio::sync::Mutex;
+
+/// Number of checks between sweeps of buckets that refilled completely and can be forgotten.
+const PRUNE_INTERVAL: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.last_refill = now;
+    }
+
+    fn is_full(&self, limit: &RateLimit) -> bool {
+        self.tokens >= limit.burst
+    }
+}
+
+/// Token bucket rate limiter keeping one bucket per key. A `None` limit lets everything through.
+#[derive(Debug)]
+pub(crate) struct RateLimiter<K> {
+    limit: Option<RateLimit>,
+    buckets: HashMap<K, TokenBucket>,
+    checks_since_prune: usize,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub(crate) fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+            checks_since_prune: 0,
+        }
+    }
+
+    /// Takes a token for `key`, returns false when the key is over its limit.
+    pub(crate) fn check(&mut self, key: K) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        self.prune(&limit, now);
+
+        let bucket = self
+            .buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(&limit));
+        bucket.refill(&limit, now);
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn prune(&mut self, limit: &RateLimit, now: Instant) {
+        self.checks_since_prune += 1;
+        if self.checks_since_prune < PRUNE_INTERVAL {
+            return;
+        }
+
+        self.checks_since_prune = 0;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(limit, now);
+            !bucket.is_full(limit)
+        });
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiters {
+    pub per_ip: Mutex<RateLimiter<IpAddr>>,
+    pub per_gamer: Mutex<RateLimiter<(SessionIdType, GamerIdType)>>,
+}
+
+impl RateLimiters {
+    pub(crate) fn new(ip_limit: Option<RateLimit>, gamer_limit: Option<RateLimit>) -> Self {
+        Self {
+            per_ip: Mutex::new(RateLimiter::new(ip_limit)),
+            per_gamer: Mutex::new(RateLimiter::new(gamer_limit)),
+        }
+    }
+
+    /// Takes a token from the IP's bucket and from the gamer's, when known. Returns false when
+    /// either is over its limit.
+    pub(crate) async fn check(
+        &self,
+        ip: Option<IpAddr>,
+        gamer: Option<(&SessionIdType, &GamerIdType)>,
+    ) -> bool {
+        if let Some(ip) = ip
+            && !self.per_ip.lock().await.check(ip)
+        {
+            return false;
+        }
+
+        match gamer {
+            Some((session_id, gamer_id)) => self
+                .per_gamer
+                .lock()
+                .await
+                .check((session_id.clone(), gamer_id.clone())),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn no_limit_lets_everything_through() {
+        let mut limiter = RateLimiter::new(None);
+        assert!((0..10_000).all(|_| limiter.check("gamer")));
+    }
+
+    #[test]
+    fn burst_is_allowed_then_refused() {
+        let mut limiter = RateLimiter::new(Some(RateLimit {
+            per_second: 0.001,
+            burst: 3.0,
+        }));
+        assert!(limiter.check("gamer"));
+        assert!(limiter.check("gamer"));
+        assert!(limiter.check("gamer"));
+        assert!(!limiter.check("gamer"));
+    }
+
+    #[test]
+    fn keys_have_their_own_buckets() {
+        let mut limiter = RateLimiter::new(Some(RateLimit {
+            per_second: 0.001,
+            burst: 1.0,
+        }));
+        assert!(limiter.check("alice"));
+        assert!(!limiter.check("alice"));
+        assert!(limiter.check("bob"));
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let mut limiter = RateLimiter::new(Some(RateLimit {
+            per_second: 100.0,
+            burst: 1.0,
+        }));
+        assert!(limiter.check("gamer"));
+        assert!(!limiter.check("gamer"));
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.check("gamer"));
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let mut limiter = RateLimiter::new(Some(RateLimit {
+            per_second: 1_000_000.0,
+            burst: 1.0,
+        }));
+        for key in 1..PRUNE_INTERVAL {
+            assert!(limiter.check(key));
+        }
+
+        // The check completing the interval sweeps the buckets refilled in the meantime.
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(limiter.check(0));
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+}
@@ -0,0 +1,25 @@
+use std::{path::Path, sync::Arc};
+
+use minignetcommon::Error;
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+
+/// Loads the PEM encoded certificate chain, server certificate first, and its private key.
+pub(crate) fn acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, Error> {
+    let certs = CertificateDer::pem_file_iter(cert_path)?.collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {:?}", cert_path).into());
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,103 @@
+use std::io::{Error as IoError, ErrorKind};
+
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use log::error;
+use minignetcommon::{read_frame, write_frame};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{Error as WsError, Message},
+};
+
+/// Receiving side of a framed connection.
+pub(crate) trait FrameRead {
+    /// Returns `None` when the peer closed the connection between frames, and fails with
+    /// `ErrorKind::InvalidData` when a frame exceeds `max_size`.
+    fn read_frame(
+        &mut self,
+        max_size: usize,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, IoError>> + Send;
+}
+
+/// Sending side of a framed connection.
+pub(crate) trait FrameWrite {
+    fn write_frame(&mut self, frame: Vec<u8>) -> impl Future<Output = Result<(), IoError>> + Send;
+
+    fn close(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+/// Frames prefixed with their length on a byte stream, eg. a TCP connection.
+pub(crate) struct LengthDelimited<T>(pub T);
+
+impl<T: AsyncRead + Unpin + Send> FrameRead for LengthDelimited<T> {
+    async fn read_frame(&mut self, max_size: usize) -> Result<Option<Vec<u8>>, IoError> {
+        read_frame(&mut self.0, max_size).await
+    }
+}
+
+impl<T: AsyncWrite + Unpin + Send> FrameWrite for LengthDelimited<T> {
+    async fn write_frame(&mut self, frame: Vec<u8>) -> Result<(), IoError> {
+        write_frame(&mut self.0, &frame).await
+    }
+
+    async fn close(&mut self) {
+        if let Err(err) = self.0.shutdown().await {
+            error!("Failed to shut down writer: {:?}", err);
+        }
+    }
+}
+
+/// Every binary WebSocket message carries one frame.
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> FrameRead for SplitStream<WebSocketStream<S>> {
+    async fn read_frame(&mut self, max_size: usize) -> Result<Option<Vec<u8>>, IoError> {
+        while let Some(message) = self.next().await {
+            match message {
+                Ok(Message::Binary(frame)) if frame.len() > max_size => {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Frame of {} bytes exceeds the limit of {}",
+                            frame.len(),
+                            max_size
+                        ),
+                    ));
+                }
+                Ok(Message::Binary(frame)) => return Ok(Some(frame.into())),
+                Ok(Message::Close(_)) => return Ok(None),
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => {}
+                Ok(Message::Text(_)) => {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        "Expected a binary message",
+                    ));
+                }
+                Err(WsError::Capacity(err)) => {
+                    return Err(IoError::new(ErrorKind::InvalidData, err));
+                }
+                Err(WsError::Io(err)) => return Err(err),
+                Err(err) => return Err(IoError::other(err)),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> FrameWrite
+    for SplitSink<WebSocketStream<S>, Message>
+{
+    async fn write_frame(&mut self, frame: Vec<u8>) -> Result<(), IoError> {
+        self.send(Message::Binary(frame.into()))
+            .await
+            .map_err(IoError::other)
+    }
+
+    async fn close(&mut self) {
+        if let Err(err) = SinkExt::close(self).await {
+            error!("Failed to close WebSocket: {:?}", err);
+        }
+    }
+}
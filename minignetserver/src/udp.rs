@@ -0,0 +1,327 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use log::{error, info, trace, warn};
+use minignetcommon::{
+    Datagram, GamerIdType, MAX_DATAGRAM_SIZE, SessionIdType, StateSnapshot, WorldState,
+    decode_untrusted_within,
+};
+use tokio::{net::UdpSocket, sync::Mutex, time::Instant};
+
+use crate::rate_limit::RateLimiters;
+
+#[derive(Debug)]
+struct UdpPeer {
+    addr: SocketAddr,
+    /// Highest snapshot sequence number relayed for the gamer.
+    last_seq: u64,
+    last_seen: Instant,
+}
+
+/// Relays state snapshots between the gamers of a session over UDP. Only the latest snapshot
+/// of every gamer matters, so stale ones are dropped instead of relayed and nothing is resent.
+/// A peer's address only moves on a datagram carrying the gamer's channel token, so a forged
+/// source address cannot redirect the snapshots of a session.
+pub(crate) struct UdpRelay {
+    socket: UdpSocket,
+    world_state: Arc<Mutex<WorldState>>,
+    /// The limits of the reliable channel, shared with it.
+    rate_limiters: Arc<RateLimiters>,
+    /// One address per gamer, the latest one it sent an accepted datagram from.
+    peers: HashMap<SessionIdType, HashMap<GamerIdType, UdpPeer>>,
+    /// Peers silent for longer are forgotten.
+    peer_timeout: Duration,
+}
+
+impl UdpRelay {
+    pub(crate) fn new(
+        socket: UdpSocket,
+        world_state: Arc<Mutex<WorldState>>,
+        rate_limiters: Arc<RateLimiters>,
+        peer_timeout: Duration,
+    ) -> Self {
+        Self {
+            socket,
+            world_state,
+            rate_limiters,
+            peers: HashMap::new(),
+            peer_timeout,
+        }
+    }
+
+    pub(crate) async fn run(mut self) {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let (size, remote_addr) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(err) => {
+                    error!("Error while receiving datagram: {:?}", err);
+                    continue;
+                }
+            };
+
+            let datagram: Datagram = match decode_untrusted_within(&buf[..size], MAX_DATAGRAM_SIZE)
+            {
+                Ok((datagram, ..)) => datagram,
+                Err(err) => {
+                    warn!("Failed decoding datagram from {}: {:?}", remote_addr, err);
+                    continue;
+                }
+            };
+
+            // Checked before anything is relayed or a subscription moves to the sender.
+            if !self
+                .rate_limiters
+                .check(Some(remote_addr.ip()), Some(datagram.gamer()))
+                .await
+            {
+                trace!("Rate limit exceeded by {}, dropping datagram", remote_addr);
+                continue;
+            }
+
+            match datagram {
+                Datagram::Subscribe(session_id, gamer_id, token) => {
+                    if self.is_member(&session_id, &gamer_id, token, &[]).await {
+                        self.touch_peer(session_id, gamer_id, remote_addr);
+                    }
+                }
+                Datagram::Update(token, snapshot) => self.relay(snapshot, token, remote_addr).await,
+                Datagram::Snapshot(_) => {
+                    warn!("Ignoring snapshot sent by {}", remote_addr);
+                }
+            }
+        }
+    }
+
+    async fn relay(&mut self, snapshot: StateSnapshot, token: u64, remote_addr: SocketAddr) {
+        if !self
+            .is_member(
+                &snapshot.session_id,
+                &snapshot.gamer_id,
+                token,
+                &snapshot.payload,
+            )
+            .await
+        {
+            return;
+        }
+
+        // Checked before touching the peer, a replayed snapshot does not move it either.
+        if let Some(peer) = self
+            .peers
+            .get(&snapshot.session_id)
+            .and_then(|peers| peers.get(&snapshot.gamer_id))
+            && snapshot.seq <= peer.last_seq
+        {
+            trace!(
+                "Dropping stale snapshot {} of {:?}, latest is {}",
+                snapshot.seq, snapshot.gamer_id, peer.last_seq
+            );
+            return;
+        }
+
+        let peer = self.touch_peer(
+            snapshot.session_id.clone(),
+            snapshot.gamer_id.clone(),
+            remote_addr,
+        );
+        peer.last_seq = snapshot.seq;
+
+        let recipients: Vec<SocketAddr> = self
+            .peers
+            .get(&snapshot.session_id)
+            .into_iter()
+            .flatten()
+            .filter(|(gamer_id, _)| **gamer_id != snapshot.gamer_id)
+            .map(|(_, peer)| peer.addr)
+            .collect();
+
+        let session_id = snapshot.session_id.clone();
+        // Relayed without the sender's token.
+        let datagram =
+            match bincode::encode_to_vec(Datagram::Snapshot(snapshot), bincode::config::standard())
+            {
+                Ok(datagram) => datagram,
+                Err(err) => {
+                    error!("Failed encoding snapshot of {:?}: {:?}", session_id, err);
+                    return;
+                }
+            };
+
+        for addr in recipients {
+            if let Err(err) = self.socket.send_to(&datagram, addr).await {
+                error!("Failed relaying snapshot to {}: {:?}", addr, err);
+            }
+        }
+    }
+
+    /// Only gamers who joined the session over the reliable channel and send their channel
+    /// token are relayed.
+    async fn is_member(
+        &self,
+        session_id: &SessionIdType,
+        gamer_id: &GamerIdType,
+        token: u64,
+        payload: &[u8],
+    ) -> bool {
+        let world_state = self.world_state.lock().await;
+        let Some(session) = world_state.session(session_id) else {
+            warn!("Ignoring datagram for unknown session {:?}", session_id);
+            return false;
+        };
+
+        if !session.has_gamer(gamer_id) {
+            warn!(
+                "Ignoring datagram from {:?}, not a gamer of {:?}",
+                gamer_id, session_id
+            );
+            return false;
+        }
+
+        if session.channel_token(gamer_id) != Some(token) {
+            warn!(
+                "Ignoring datagram from {:?} of {:?} with a wrong token",
+                gamer_id, session_id
+            );
+            return false;
+        }
+
+        session.check_payload_size(payload).is_ok()
+    }
+
+    /// Records the address the gamer last sent from and forgets the silent peers of the session.
+    fn touch_peer(
+        &mut self,
+        session_id: SessionIdType,
+        gamer_id: GamerIdType,
+        remote_addr: SocketAddr,
+    ) -> &mut UdpPeer {
+        let now = Instant::now();
+        let peer_timeout = self.peer_timeout;
+        let peers = self.peers.entry(session_id).or_default();
+        peers.retain(|_, peer| now.duration_since(peer.last_seen) < peer_timeout);
+
+        let peer = peers.entry(gamer_id).or_insert_with(|| UdpPeer {
+            addr: remote_addr,
+            last_seq: 0,
+            last_seen: now,
+        });
+        if peer.addr != remote_addr {
+            info!("UDP peer moved from {} to {}", peer.addr, remote_addr);
+            peer.addr = remote_addr;
+        }
+        peer.last_seen = now;
+
+        peer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minignetcommon::{MessageLimits, Operation, Response};
+    use tokio::time::timeout;
+
+    use super::*;
+
+    const SESSION: &str = "game";
+
+    fn encode(datagram: Datagram) -> Vec<u8> {
+        bincode::encode_to_vec(datagram, bincode::config::standard()).unwrap()
+    }
+
+    fn update(token: u64, gamer_id: &str, seq: u64) -> Vec<u8> {
+        encode(Datagram::Update(
+            token,
+            StateSnapshot {
+                session_id: SESSION.to_string(),
+                gamer_id: gamer_id.to_string(),
+                seq,
+                payload: vec![seq as u8],
+            },
+        ))
+    }
+
+    async fn received(socket: &UdpSocket) -> Option<Datagram> {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let size = timeout(Duration::from_millis(200), socket.recv(&mut buf))
+            .await
+            .ok()?
+            .unwrap();
+        Some(
+            decode_untrusted_within(&buf[..size], MAX_DATAGRAM_SIZE)
+                .unwrap()
+                .0,
+        )
+    }
+
+    async fn client(relay_addr: SocketAddr) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(relay_addr).await.unwrap();
+        socket
+    }
+
+    #[tokio::test]
+    async fn datagrams_without_the_token_do_not_move_a_peer() {
+        let mut world_state = WorldState::new(MessageLimits::default());
+        let mut tokens = HashMap::new();
+        for gamer_id in ["alice", "bob"] {
+            world_state.handle(Operation::JoinSession(
+                SESSION.to_string(),
+                gamer_id.to_string(),
+            ));
+            let Response::OkWithToken(token) = world_state.handle(Operation::GetChannelToken(
+                SESSION.to_string(),
+                gamer_id.to_string(),
+            )) else {
+                panic!("No channel token for {}", gamer_id);
+            };
+            tokens.insert(gamer_id, token);
+        }
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = socket.local_addr().unwrap();
+        let relay = UdpRelay::new(
+            socket,
+            Arc::new(Mutex::new(world_state)),
+            Arc::new(RateLimiters::new(None, None)),
+            Duration::from_secs(60),
+        );
+        let relay = tokio::spawn(relay.run());
+
+        let alice = client(relay_addr).await;
+        let bob = client(relay_addr).await;
+        let attacker = client(relay_addr).await;
+
+        bob.send(&update(tokens["bob"], "bob", 10)).await.unwrap();
+        attacker
+            .send(&encode(Datagram::Subscribe(
+                SESSION.to_string(),
+                "bob".to_string(),
+                tokens["bob"] + 1,
+            )))
+            .await
+            .unwrap();
+        attacker
+            .send(&update(tokens["bob"] + 1, "bob", 11))
+            .await
+            .unwrap();
+        bob.send(&update(tokens["bob"], "bob", 9)).await.unwrap();
+
+        alice
+            .send(&update(tokens["alice"], "alice", 1))
+            .await
+            .unwrap();
+        match received(&bob).await {
+            Some(Datagram::Snapshot(snapshot)) => {
+                assert_eq!((snapshot.gamer_id.as_str(), snapshot.seq), ("alice", 1))
+            }
+            datagram => panic!("Unexpected datagram {:?}", datagram),
+        }
+        assert!(received(&attacker).await.is_none());
+        // Bob's first update was relayed before alice subscribed, the stale and forged ones
+        // were dropped.
+        assert!(received(&alice).await.is_none());
+
+        relay.abort();
+    }
+}